@@ -0,0 +1,485 @@
+use bevy::{input::keyboard::KeyboardInput, prelude::*};
+use bevy::input::InputSystem;
+use bevy::reflect::{ DynamicEnum, DynamicVariant, Enum, TypeInfo, Typed, VariantInfo };
+use serde::{ Deserialize, Serialize };
+use std::collections::HashMap;
+use std::fs;
+
+use crate::players::PlayersConfig;
+use crate::snake::Direction;
+use crate::state::asset_path;
+
+// actions plugin: keyboard keys and gamepads in, actions out.
+// turns belong to a snake, confirm and pause can come from anyone.
+// gamepads are handed out as they connect, first to snakes with no keys, then to the
+// lowest snake id without one, and freed again when they disconnect.
+// runs before Update, so every system there sees this frame's actions.
+// key bindings are read from save_data/controls.ron, keys by their KeyCode name.
+// a snake whose keys are all blank ("") has none, and needs a gamepad:
+//
+// (
+//     snakes: [
+//         (up: "ArrowUp", down: "ArrowDown", left: "ArrowLeft", right: "ArrowRight"),
+//         (up: "KeyW", down: "KeyS", left: "KeyA", right: "KeyD"),
+//     ],
+//     confirm: ["Space", "Enter"],
+//     pause: ["Escape", "KeyP"],
+// )
+
+const CONTROLS_PATH: &str = "save_data/controls.ron";
+
+const STICK_THRESHOLD: f32 = 0.5;
+
+// start screen keys. they can't be rebound, so the editor and the controls screen can always
+// be reached - and no binding may share them.
+pub const EDIT_STAGE_KEY: KeyCode = KeyCode::KeyE;
+pub const CONTROLS_KEY: KeyCode = KeyCode::KeyC;
+
+pub struct ActionsPlugin;
+
+impl Plugin for ActionsPlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(Controls::load());
+		app.init_resource::<GamepadAssignments>();
+		app.add_event::<ActionEvent>();
+		app.add_systems(PreUpdate, (assign_gamepads, read_keyboard, read_gamepads).chain().after(InputSystem));
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+	Turn(Direction),
+	Confirm, // start, continue, fast-forward
+	Pause,
+}
+
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct ActionEvent {
+	pub snake_id: Option<u32>, // None for keys that don't belong to a snake
+	pub action: Action,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnakeKeys {
+	pub up: KeyCode,
+	pub down: KeyCode,
+	pub left: KeyCode,
+	pub right: KeyCode,
+}
+
+impl SnakeKeys {
+	const fn new(up: KeyCode, down: KeyCode, left: KeyCode, right: KeyCode) -> Self {
+		Self { up, down, left, right }
+	}
+
+	pub fn all(&self) -> [(Direction, KeyCode); 4] {
+		[(Direction::Up, self.up), (Direction::Down, self.down), (Direction::Left, self.left), (Direction::Right, self.right)]
+	}
+
+	pub fn get(&self, direction: Direction) -> Option<KeyCode> {
+		self.all().iter().find(|(d, _)| *d == direction).map(|(_, key_code)| *key_code)
+	}
+
+	pub fn set(&mut self, direction: Direction, key_code: KeyCode) {
+		match direction {
+			Direction::Up => self.up = key_code,
+			Direction::Down => self.down = key_code,
+			Direction::Left => self.left = key_code,
+			Direction::Right => self.right = key_code,
+			Direction::None => {}
+		}
+	}
+
+	fn direction(&self, key_code: KeyCode) -> Option<Direction> {
+		if key_code == self.up { Some(Direction::Up) }
+		else if key_code == self.down { Some(Direction::Down) }
+		else if key_code == self.left { Some(Direction::Left) }
+		else if key_code == self.right { Some(Direction::Right) }
+		else { None }
+	}
+}
+
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct Controls {
+	pub snakes: Vec<Option<SnakeKeys>>, // by snake id, from 1. None, or past the end: no keys, that snake needs a gamepad
+	pub confirm: Vec<KeyCode>,
+	pub pause: Vec<KeyCode>,
+}
+
+impl Default for Controls {
+	fn default() -> Self {
+		Self {
+			snakes: vec![
+				// a shared keyboard runs out after six.
+				Some(SnakeKeys::new(KeyCode::ArrowUp, KeyCode::ArrowDown, KeyCode::ArrowLeft, KeyCode::ArrowRight)),
+				Some(SnakeKeys::new(KeyCode::KeyW, KeyCode::KeyS, KeyCode::KeyA, KeyCode::KeyD)),
+				Some(SnakeKeys::new(KeyCode::KeyI, KeyCode::KeyK, KeyCode::KeyJ, KeyCode::KeyL)),
+				Some(SnakeKeys::new(KeyCode::Numpad8, KeyCode::Numpad5, KeyCode::Numpad4, KeyCode::Numpad6)),
+				Some(SnakeKeys::new(KeyCode::KeyT, KeyCode::KeyG, KeyCode::KeyF, KeyCode::KeyH)),
+				Some(SnakeKeys::new(KeyCode::Home, KeyCode::End, KeyCode::Delete, KeyCode::PageDown)),
+			],
+			confirm: vec![KeyCode::Space, KeyCode::Enter],
+			pause: vec![KeyCode::Escape, KeyCode::KeyP],
+		}
+	}
+}
+
+impl Controls {
+	pub fn load() -> Self {
+		let Ok(text) = fs::read_to_string(asset_path(CONTROLS_PATH)) else {
+			println!("actions: no {} found, using default controls", CONTROLS_PATH);
+			return Self::default();
+		};
+		match ron::from_str::<ControlsFile>(&text) {
+			Ok(file) => file.into(),
+			Err(error) => {
+				println!("actions: could not read {}, using default controls: {}", CONTROLS_PATH, error);
+				Self::default()
+			}
+		}
+	}
+
+	pub fn save(&self) -> Result<(), String> {
+		let text = ron::ser::to_string_pretty(&ControlsFile::from(self), ron::ser::PrettyConfig::default())
+			.map_err(|error| error.to_string())?;
+		fs::write(asset_path(CONTROLS_PATH), text + "\n").map_err(|error| error.to_string())?;
+		println!("actions: saved {}", CONTROLS_PATH);
+		Ok(())
+	}
+
+	// every key bound to more than one thing, with what it's bound to - e.g. "KeyA: snake 2 left, confirm".
+	// only the first `snakes` snakes count, the keys of snakes that aren't playing can't get in the way.
+	pub fn conflicts(&self, snakes: usize) -> Vec<String> {
+		let mut uses: Vec<(KeyCode, Vec<String>)> = vec![];
+		let mut add = |key_code: KeyCode, binding: String| {
+			match uses.iter_mut().find(|(used, _)| *used == key_code) {
+				Some((_, bindings)) => bindings.push(binding),
+				None => uses.push((key_code, vec![binding])),
+			}
+		};
+		for (i, keys) in self.snakes.iter().take(snakes).enumerate() {
+			let Some(keys) = keys else { continue; };
+			for (direction, key_code) in keys.all() {
+				add(key_code, format!("snake {} {}", i + 1, direction_name(direction)));
+			}
+		}
+		for key_code in &self.confirm { add(*key_code, String::from("confirm")); }
+		for key_code in &self.pause { add(*key_code, String::from("pause")); }
+		add(EDIT_STAGE_KEY, String::from("edit stage"));
+		add(CONTROLS_KEY, String::from("controls"));
+
+		uses.into_iter()
+			.filter(|(_, bindings)| bindings.len() > 1)
+			.map(|(key_code, bindings)| format!("{}: {}", key_name(key_code), bindings.join(", ")))
+			.collect()
+	}
+
+	pub fn has_keys(&self, snake_id: u32) -> bool {
+		self.keys(snake_id).is_some()
+	}
+
+	pub fn keys(&self, snake_id: u32) -> Option<&SnakeKeys> {
+		let i = (snake_id as usize).checked_sub(1)?;
+		self.snakes.get(i)?.as_ref()
+	}
+
+	fn actions_for_key(&self, key_code: KeyCode) -> Vec<ActionEvent> {
+		let mut actions: Vec<ActionEvent> = vec![];
+		for (i, keys) in self.snakes.iter().enumerate() {
+			if let Some(direction) = keys.and_then(|keys| keys.direction(key_code)) {
+				actions.push(ActionEvent { snake_id: Some(i as u32 + 1), action: Action::Turn(direction) });
+			}
+		}
+		if self.confirm.contains(&key_code) { actions.push(ActionEvent { snake_id: None, action: Action::Confirm }); }
+		if self.pause.contains(&key_code) { actions.push(ActionEvent { snake_id: None, action: Action::Pause }); }
+		actions
+	}
+}
+
+pub fn direction_name(direction: Direction) -> &'static str {
+	match direction {
+		Direction::Up => "up",
+		Direction::Down => "down",
+		Direction::Left => "left",
+		Direction::Right => "right",
+		Direction::None => "none",
+	}
+}
+
+// keys go to and from the file by their variant name, e.g. "KeyW" or "ArrowUp".
+pub fn key_name(key_code: KeyCode) -> String {
+	key_code.variant_name().to_string()
+}
+
+// from_reflect panics on a variant KeyCode doesn't have, so that's checked first.
+fn parse_key(name: &str) -> Option<KeyCode> {
+	let TypeInfo::Enum(info) = KeyCode::type_info() else { return None; };
+	let Some(VariantInfo::Unit(_)) = info.variant(name) else { return None; };
+	KeyCode::from_reflect(&DynamicEnum::new(name, DynamicVariant::Unit))
+}
+
+// the file side of Controls. unknown key names keep the default binding, blank ones mean no keys.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct ControlsFile {
+	snakes: Vec<SnakeKeysFile>,
+	confirm: Vec<String>,
+	pause: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct SnakeKeysFile {
+	up: String,
+	down: String,
+	left: String,
+	right: String,
+}
+
+impl From<&Controls> for ControlsFile {
+	fn from(controls: &Controls) -> Self {
+		Self {
+			snakes: controls.snakes.iter().map(|keys| match keys {
+				Some(keys) => SnakeKeysFile {
+					up: key_name(keys.up),
+					down: key_name(keys.down),
+					left: key_name(keys.left),
+					right: key_name(keys.right),
+				},
+				None => SnakeKeysFile { up: String::new(), down: String::new(), left: String::new(), right: String::new() },
+			}).collect(),
+			confirm: controls.confirm.iter().map(|key_code| key_name(*key_code)).collect(),
+			pause: controls.pause.iter().map(|key_code| key_name(*key_code)).collect(),
+		}
+	}
+}
+
+impl From<ControlsFile> for Controls {
+	fn from(file: ControlsFile) -> Self {
+		let defaults = Controls::default();
+		let key = |name: &str, default: Option<KeyCode>| -> Option<KeyCode> {
+			let key_code = parse_key(name);
+			if key_code.is_none() { println!("actions: unknown key {:?} in {}", name, CONTROLS_PATH); }
+			key_code.or(default)
+		};
+
+		// a snake that can't get all four keys goes without, the ones after it keep theirs.
+		let mut snakes: Vec<Option<SnakeKeys>> = vec![];
+		let mut lost: Vec<String> = vec![];
+		for (i, keys) in file.snakes.iter().enumerate() {
+			let names = [&keys.up, &keys.down, &keys.left, &keys.right];
+			if names.iter().all(|name| name.is_empty()) {
+				snakes.push(None);
+				continue;
+			}
+			let default = defaults.keys(i as u32 + 1);
+			let (Some(up), Some(down), Some(left), Some(right)) = (
+				key(&keys.up, default.map(|d| d.up)),
+				key(&keys.down, default.map(|d| d.down)),
+				key(&keys.left, default.map(|d| d.left)),
+				key(&keys.right, default.map(|d| d.right)),
+			) else {
+				lost.push((i + 1).to_string());
+				snakes.push(None);
+				continue;
+			};
+			snakes.push(Some(SnakeKeys::new(up, down, left, right)));
+		}
+		if !lost.is_empty() {
+			println!("actions: no usable keys for snake(s) {} in {}, they need a gamepad", lost.join(", "), CONTROLS_PATH);
+		}
+
+		let keys = |names: &[String], default: &[KeyCode]| -> Vec<KeyCode> {
+			let key_codes: Vec<KeyCode> = names.iter().filter_map(|name| key(name, None)).collect();
+			if key_codes.is_empty() { default.to_vec() } else { key_codes }
+		};
+
+		Self {
+			snakes,
+			confirm: keys(&file.confirm, &defaults.confirm),
+			pause: keys(&file.pause, &defaults.pause),
+		}
+	}
+}
+
+#[derive(Resource, Default)]
+struct GamepadAssignments {
+	snakes: HashMap<Entity, u32>, // gamepad entity -> snake id
+	stick_directions: HashMap<Entity, Direction>, // last direction each stick was pushed, to turn once per push
+}
+
+// the snake a new gamepad goes to: snakes with no keys can't play without one, so they come first.
+fn free_snake(assigned: &HashMap<Entity, u32>, players: &PlayersConfig, controls: &Controls) -> Option<u32> {
+	let free = |id: &u32| !assigned.values().any(|assigned_id| assigned_id == id);
+	players.ids().filter(free).find(|id| !controls.has_keys(*id))
+		.or_else(|| players.ids().find(free))
+}
+
+// the axis pushed furthest past the threshold, stick up is up on screen.
+fn stick_direction(stick: Vec2) -> Direction {
+	if stick.x.abs().max(stick.y.abs()) < STICK_THRESHOLD { return Direction::None; }
+	if stick.x.abs() > stick.y.abs() {
+		if stick.x > 0.0 { Direction::Right } else { Direction::Left }
+	} else if stick.y > 0.0 { Direction::Up } else { Direction::Down }
+}
+
+fn assign_gamepads(
+	mut assignments: ResMut<GamepadAssignments>,
+	players: Res<PlayersConfig>,
+	controls: Res<Controls>,
+	gamepads: Query<Entity, With<Gamepad>>,
+) {
+	let connected: Vec<Entity> = gamepads.iter().collect();
+	assignments.snakes.retain(|gamepad, snake_id| {
+		let keep = connected.contains(gamepad);
+		if !keep { println!("actions: gamepad for snake {} disconnected", snake_id); }
+		keep
+	});
+	assignments.stick_directions.retain(|gamepad, _| connected.contains(gamepad));
+
+	for gamepad in connected {
+		if assignments.snakes.contains_key(&gamepad) { continue; }
+		// more gamepads than snakes: it can still confirm and pause.
+		let Some(snake_id) = free_snake(&assignments.snakes, &players, &controls) else { continue; };
+		println!("actions: gamepad assigned to snake {}", snake_id);
+		assignments.snakes.insert(gamepad, snake_id);
+	}
+}
+
+fn read_keyboard(
+	mut key_events: EventReader<KeyboardInput>,
+	mut action_writer: EventWriter<ActionEvent>,
+	controls: Res<Controls>,
+) {
+	for e in key_events.read().filter(|e| e.state.is_pressed() && !e.repeat) {
+		for action in controls.actions_for_key(e.key_code) {
+			action_writer.write(action);
+		}
+	}
+}
+
+fn read_gamepads(
+	mut assignments: ResMut<GamepadAssignments>,
+	mut action_writer: EventWriter<ActionEvent>,
+	gamepads: Query<(Entity, &Gamepad)>,
+) {
+	for (entity, gamepad) in &gamepads {
+		let snake_id = assignments.snakes.get(&entity).copied();
+
+		let dpad = [
+			(GamepadButton::DPadUp, Direction::Up),
+			(GamepadButton::DPadDown, Direction::Down),
+			(GamepadButton::DPadLeft, Direction::Left),
+			(GamepadButton::DPadRight, Direction::Right),
+		];
+		for (button, direction) in dpad {
+			if gamepad.just_pressed(button) {
+				action_writer.write(ActionEvent { snake_id, action: Action::Turn(direction) });
+			}
+		}
+
+		let direction = stick_direction(gamepad.left_stick());
+		let previous = assignments.stick_directions.insert(entity, direction).unwrap_or(Direction::None);
+		if direction != Direction::None && direction != previous {
+			action_writer.write(ActionEvent { snake_id, action: Action::Turn(direction) });
+		}
+
+		if gamepad.just_pressed(GamepadButton::South) {
+			action_writer.write(ActionEvent { snake_id: None, action: Action::Confirm });
+		}
+		if gamepad.just_pressed(GamepadButton::Start) {
+			action_writer.write(ActionEvent { snake_id: None, action: Action::Pause });
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn keys_map_to_their_snake() {
+		let controls = Controls::default();
+		assert_eq!(controls.actions_for_key(KeyCode::KeyA), vec![ActionEvent { snake_id: Some(2), action: Action::Turn(Direction::Left) }]);
+		assert_eq!(controls.actions_for_key(KeyCode::Enter), vec![ActionEvent { snake_id: None, action: Action::Confirm }]);
+		assert!(controls.actions_for_key(KeyCode::KeyQ).is_empty());
+		assert!(controls.has_keys(6));
+		assert!(!controls.has_keys(7));
+		assert!(!controls.has_keys(0));
+	}
+
+	#[test]
+	fn controls_file_round_trips() {
+		let path = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/save_data/controls.ron");
+		let file: ControlsFile = ron::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+		let controls = Controls::from(file.clone());
+		assert_eq!(controls, Controls::default());
+		assert_eq!(ControlsFile::from(&controls), file);
+	}
+
+	#[test]
+	fn a_bad_snake_only_loses_its_own_keys() {
+		let keys = |up: &str, down: &str, left: &str, right: &str| SnakeKeysFile {
+			up: up.to_string(), down: down.to_string(), left: left.to_string(), right: right.to_string(),
+		};
+		let mut file = ControlsFile::from(&Controls::default());
+		file.snakes.push(keys("KeyZ", "KeyX", "KeyQQ", "KeyV")); // snake 7, no default to fall back to
+		file.snakes.push(keys("Digit7", "Digit8", "Digit9", "Digit0"));
+		file.snakes[2] = keys("", "", "", "");
+
+		let controls = Controls::from(file.clone());
+		assert!(controls.has_keys(2));
+		assert!(!controls.has_keys(3));
+		assert!(controls.has_keys(4));
+		assert!(!controls.has_keys(7));
+		assert_eq!(controls.keys(8), Some(&SnakeKeys::new(KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9, KeyCode::Digit0)));
+		assert_eq!(controls.actions_for_key(KeyCode::Digit9), vec![ActionEvent { snake_id: Some(8), action: Action::Turn(Direction::Left) }]);
+		assert!(controls.actions_for_key(KeyCode::KeyI).is_empty());
+
+		// keyless snakes are saved blank, and stay that way.
+		assert_eq!(ControlsFile::from(&controls).snakes[2], keys("", "", "", ""));
+	}
+
+	#[test]
+	fn shared_keys_are_conflicts() {
+		let mut controls = Controls::default();
+		assert!(controls.conflicts(6).is_empty());
+
+		controls.snakes[0].as_mut().unwrap().set(Direction::Left, KeyCode::KeyA);
+		controls.confirm.push(KeyCode::KeyA);
+		assert_eq!(controls.conflicts(6), vec![String::from("KeyA: snake 1 left, snake 2 left, confirm")]);
+		assert_eq!(controls.conflicts(1), vec![String::from("KeyA: snake 1 left, confirm")]);
+	}
+
+	#[test]
+	fn sticks_turn_along_the_axis_pushed_furthest() {
+		assert_eq!(stick_direction(Vec2::new(0.2, 0.1)), Direction::None);
+		assert_eq!(stick_direction(Vec2::new(0.9, 0.6)), Direction::Right);
+		assert_eq!(stick_direction(Vec2::new(0.3, -0.8)), Direction::Down);
+	}
+
+	#[test]
+	fn gamepads_go_to_the_lowest_free_snake() {
+		let players = PlayersConfig { count: 3 };
+		let controls = Controls::default();
+		let mut assigned: HashMap<Entity, u32> = HashMap::new();
+		assigned.insert(Entity::from_raw(1), 1);
+		assigned.insert(Entity::from_raw(2), 3);
+		assert_eq!(free_snake(&assigned, &players, &controls), Some(2));
+
+		assigned.insert(Entity::from_raw(3), 2);
+		assert_eq!(free_snake(&assigned, &players, &controls), None);
+	}
+
+	#[test]
+	fn gamepads_go_to_snakes_without_keys_first() {
+		let players = PlayersConfig { count: 8 };
+		let controls = Controls::default();
+		let mut assigned: HashMap<Entity, u32> = HashMap::new();
+		assert_eq!(free_snake(&assigned, &players, &controls), Some(7));
+
+		assigned.insert(Entity::from_raw(1), 7);
+		assert_eq!(free_snake(&assigned, &players, &controls), Some(8));
+
+		assigned.insert(Entity::from_raw(2), 8);
+		assert_eq!(free_snake(&assigned, &players, &controls), Some(1));
+	}
+}
@@ -1,7 +1,6 @@
 use bevy::prelude::*;
-use rand::prelude::*;
-
-use crate::stage::{ StageCoordinate };
+use rand::Rng;
+use rand::rngs::StdRng;
 
 const TUMBLE_MAX_TRANSLATION: i32 = 100;
 const TUMBLE_MAX_ROTATION: i32 = 9;
@@ -11,7 +10,7 @@ pub struct AnimPlugin;
 impl Plugin for AnimPlugin {
 	fn build(&self, app: &mut App) {
 		app.add_systems(Update, (
-			update_tumble_anims, update_oscillate_anims
+			update_tumble_anims, update_oscillate_anims, update_slide_anims, update_shrink_anims
 		));
 	}
 }
@@ -24,17 +23,18 @@ pub struct TumbleAnim {
 }
 
 impl TumbleAnim {
-	pub fn new(speed: f32, unipolar_y: bool) -> Self {
+	// rng is the visual stream of GameRng.
+	pub fn new(speed: f32, unipolar_y: bool, rng: &mut StdRng) -> Self {
 		let y_min = if unipolar_y { 0 } else { -TUMBLE_MAX_TRANSLATION };
 		let max = TUMBLE_MAX_TRANSLATION;
-		let t_x = rand::random_range(-max..max) as f32 / 100.0;
-		let t_y = rand::random_range(y_min..max) as f32 / 100.0;
-		let t_z = rand::random_range(-max..max) as f32 / 100.0;
+		let t_x = rng.random_range(-max..max) as f32 / 100.0;
+		let t_y = rng.random_range(y_min..max) as f32 / 100.0;
+		let t_z = rng.random_range(-max..max) as f32 / 100.0;
 
 		let max = TUMBLE_MAX_ROTATION;
-		let r_x = rand::random_range(-max..max) as f32;
-		let r_y = rand::random_range(-max..max) as f32;
-		let r_z = rand::random_range(-max..max) as f32;
+		let r_x = rng.random_range(-max..max) as f32;
+		let r_y = rng.random_range(-max..max) as f32;
+		let r_z = rng.random_range(-max..max) as f32;
 		
 		Self {
 			translation: Vec3::new(t_x, t_y, t_z),
@@ -42,6 +42,34 @@ impl TumbleAnim {
 			speed,
 		}
 	}
+
+	// same tumble, but always heading down.
+	pub fn falling(speed: f32, rng: &mut StdRng) -> Self {
+		let mut anim = Self::new(speed, true, rng);
+		anim.translation.y = -anim.translation.y - 1.0;
+		anim
+	}
+}
+
+// moves towards a target translation and removes itself when it gets there.
+#[derive(Component)]
+pub struct SlideAnim {
+	target: Vec3,
+	speed: f32,
+}
+
+impl SlideAnim {
+	pub fn new(target: Vec3, speed: f32) -> Self { Self { target, speed } }
+}
+
+// scales down to nothing, then despawns the entity.
+#[derive(Component)]
+pub struct ShrinkAnim {
+	speed: f32,
+}
+
+impl ShrinkAnim {
+	pub fn new(speed: f32) -> Self { Self { speed } }
 }
 
 #[derive(Component)]
@@ -51,6 +79,7 @@ pub struct OscillateAnim {
 	amplitude: Vec3,
 }
 
+#[allow(dead_code)] // currently unused, see commented out death anim in stage.rs
 impl OscillateAnim {
 	pub fn new(translation: Vec3, speed: Vec3, amplitude: Vec3) -> Self { Self {translation, speed, amplitude} }
 }
@@ -79,3 +108,35 @@ fn update_oscillate_anims(
 		transform.translation = Vec3::new(x, y, z);
 	}
 }
+
+fn update_slide_anims(
+	time: Res<Time>,
+	mut commands: Commands,
+	query: Query<(Entity, &mut Transform, &SlideAnim)>,
+) {
+	for (entity, mut transform, slide) in query {
+		let step = slide.speed * time.delta_secs();
+		let to_target = slide.target - transform.translation;
+		if to_target.length() <= step {
+			transform.translation = slide.target;
+			commands.entity(entity).remove::<SlideAnim>();
+		} else {
+			transform.translation += to_target.normalize() * step;
+		}
+	}
+}
+
+fn update_shrink_anims(
+	time: Res<Time>,
+	mut commands: Commands,
+	query: Query<(Entity, &mut Transform, &ShrinkAnim)>,
+) {
+	for (entity, mut transform, shrink) in query {
+		let step = shrink.speed * time.delta_secs();
+		if transform.scale.max_element() <= step {
+			commands.entity(entity).despawn();
+		} else {
+			transform.scale = (transform.scale - Vec3::splat(step)).max(Vec3::ZERO);
+		}
+	}
+}
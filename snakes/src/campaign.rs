@@ -0,0 +1,151 @@
+use serde::Deserialize;
+use std::fs;
+
+use crate::generator::GeneratorParams;
+use crate::metadata::SnackPlacement;
+use crate::state::asset_path;
+
+// campaign: the ordered list of stages played from start to finish.
+// read from a RON manifest, ids point at stage_<id>.txt layouts,
+// or at just the stage_<id>.ron metadata for stages with a generator.
+// endless keeps generating stages after the last one, bumping the seed each time.
+// snack_placement applies to every stage that doesn't set its own:
+//
+// (
+//     stages: [
+//         (id: 0, title: "snakes"),
+//         (id: 1, title: "first snack"),
+//         (id: 50, title: "somewhere new", generator: (seed: 3, width: 12, height: 10, wall_density: 0.2, holes: 2, players: 3)),
+//     ],
+//     endless: (id: 100, title: "endless", generator: (seed: 1, width: 14, height: 12, wall_density: 0.25, holes: 3, players: 3)),
+//     snack_placement: Fair(3),
+// )
+
+const CAMPAIGN_PATH: &str = "campaign.ron";
+const STAGE_LAYOUTS_PATH: &str = "stage_layouts";
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct CampaignStage {
+	pub id: u32,
+	#[serde(default)]
+	pub title: Option<String>,
+	#[serde(default)]
+	pub generator: Option<GeneratorParams>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Campaign {
+	pub stages: Vec<CampaignStage>,
+	#[serde(default)]
+	pub endless: Option<CampaignStage>, // needs a generator, ignored otherwise
+	#[serde(default)]
+	pub snack_placement: Option<SnackPlacement>,
+}
+
+impl Campaign {
+	pub fn load() -> Self {
+		let campaign = match fs::read_to_string(asset_path(CAMPAIGN_PATH)) {
+			Ok(text) => match Self::parse(&text) {
+				Ok(campaign) => campaign,
+				Err(error) => {
+					println!("campaign: could not read {}, using stage layouts folder: {}", CAMPAIGN_PATH, error);
+					Self::from_layouts_folder()
+				}
+			},
+			Err(_) => {
+				println!("campaign: no manifest found, using stage layouts folder");
+				Self::from_layouts_folder()
+			}
+		};
+
+		if campaign.stages.is_empty() {
+			println!("campaign: no stages found, falling back to stage 0");
+			return Self { stages: vec![CampaignStage { id: 0, title: None, generator: None }], endless: None, ..campaign };
+		}
+		if campaign.endless.as_ref().is_some_and(|endless| endless.generator.is_none()) {
+			println!("campaign: endless stage has no generator, ignoring it");
+			return Self { endless: None, ..campaign };
+		}
+		campaign
+	}
+
+	pub fn parse(text: &str) -> Result<Self, ron::error::SpannedError> {
+		ron::Options::default()
+			.with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+			.from_str(text)
+	}
+
+	// fallback: every stage_<id>.txt in the layouts folder, ordered by id.
+	// anything else in the folder (metadata, backups) is ignored.
+	fn from_layouts_folder() -> Self {
+		let Ok(dir) = fs::read_dir(asset_path(STAGE_LAYOUTS_PATH)) else {
+			println!("campaign: stage layouts folder {} not found", STAGE_LAYOUTS_PATH);
+			return Self::default();
+		};
+
+		let mut ids: Vec<u32> = dir
+			.filter_map(|entry| entry.ok())
+			.filter_map(|entry| {
+				let file_name = entry.file_name().into_string().ok()?;
+				file_name.strip_prefix("stage_")?.strip_suffix(".txt")?.parse::<u32>().ok()
+			})
+			.collect();
+		ids.sort();
+
+		Self {
+			stages: ids.into_iter().map(|id| CampaignStage { id, title: None, generator: None }).collect(),
+			..Self::default()
+		}
+	}
+
+	// stages past the end of the list come from the endless template, seed offset by how far past.
+	pub fn stage_at(&self, index: usize) -> CampaignStage {
+		if let Some(stage) = self.stages.get(index) { return stage.clone(); }
+
+		let endless = self.endless.as_ref().expect("campaign index past the last stage without an endless stage");
+		let offset = (index - self.stages.len()) as u64;
+		CampaignStage {
+			generator: endless.generator.map(|params| params.with_seed(params.seed.wrapping_add(offset))),
+			..endless.clone()
+		}
+	}
+
+	pub fn index_of(&self, stage_id: u32) -> Option<usize> {
+		self.stages.iter().position(|stage| stage.id == stage_id)
+	}
+
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::path::Path;
+
+	#[test]
+	fn campaign_manifest_points_at_existing_layouts() {
+		let path = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/campaign.ron");
+		let campaign = Campaign::parse(&fs::read_to_string(path).unwrap()).unwrap();
+		assert!(!campaign.stages.is_empty());
+
+		for (i, stage) in campaign.stages.iter().enumerate() {
+			assert_eq!(campaign.index_of(stage.id), Some(i), "stage {} is listed more than once", stage.id);
+			if stage.generator.is_some() { continue; }
+
+			let layout = format!("{}/assets/stage_layouts/stage_{}.txt", env!("CARGO_MANIFEST_DIR"), stage.id);
+			assert!(Path::new(&layout).exists(), "campaign stage {} has no layout {}", stage.id, layout);
+		}
+	}
+
+	#[test]
+	fn endless_stages_bump_the_seed() {
+		let campaign = Campaign::parse("(
+			stages: [(id: 0)],
+			endless: (id: 100, generator: (seed: 10, width: 8, height: 8, wall_density: 0.1, holes: 1, players: 2)),
+		)").unwrap();
+
+		assert_eq!(campaign.stage_at(0).generator, None);
+		assert_eq!(campaign.stage_at(1).generator.unwrap().seed, 10);
+		assert_eq!(campaign.stage_at(3).generator.unwrap().seed, 12);
+		assert_eq!(campaign.stage_at(3).id, 100);
+	}
+}
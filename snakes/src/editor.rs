@@ -0,0 +1,344 @@
+use bevy::input::keyboard::KeyboardInput;
+use bevy::input::ButtonState;
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use std::fs;
+
+use crate::layout::{ is_known_tile, spawn_point_id, validate_layout, MAX_SPAWN_POINTS };
+use crate::shared_assets::SharedAssets;
+use crate::snacks::SnackKind;
+use crate::snake::snake_color;
+use crate::stage::{ StageCoordinate, StageEvent, StageEventData, LAYOUT_FILEPATH };
+use crate::state::{ asset_path, GameState, GameStateData, GameStateEvent };
+use crate::ui::UIEvent;
+
+// editor plugin: paint the current stage layout in place, reached from the start screen.
+// painting goes through stage events, so the stage spawns the same tiles it sets for play.
+//
+// arrows or mouse: move the cursor
+// type a tile character: paint it, and keep it as the mouse brush
+// space / enter / left click: paint the brush - right click: paint void
+// ctrl+s: save - esc: back to the stage, unsaved edits are dropped
+
+const CURSOR_Y: f32 = 1.05;
+const CURSOR_SIZE: Vec3 = Vec3::new(1.0, 0.08, 1.0);
+const CURSOR_COLOR: Color = Color::srgba(1.0, 1.0, 1.0, 0.35);
+const MARKER_Y: f32 = 1.4;
+const MARKER_SIZE: Vec3 = Vec3::new(0.6, 0.5, 0.6);
+const HIDDEN_TRANSLATION: Vec3 = Vec3::new(1000.0, 0.0, 1000.0);
+const FLOOR_TILE: char = 'A';
+const VOID_TILE: char = '_';
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_systems(Update, (
+			read_gamestate_events,
+			move_cursor,
+			paint_tiles,
+			save_layout,
+			update_cursor_and_markers,
+		).chain());
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct EditData {
+	pub rows: Vec<String>, // filled in by the stage once editing starts
+	pub cursor: StageCoordinate,
+	pub brush: char,
+	pub unsaved: bool,
+}
+
+impl EditData {
+	pub fn new() -> Self {
+		Self { rows: vec![], cursor: StageCoordinate::new(0, 0), brush: FLOOR_TILE, unsaved: false }
+	}
+
+	pub fn width(&self) -> usize {
+		self.rows.iter().map(|row| row.chars().count()).max().unwrap_or(0)
+	}
+
+	pub fn height(&self) -> usize {
+		self.rows.len()
+	}
+
+	pub fn contains(&self, coordinate: &StageCoordinate) -> bool {
+		coordinate.x >= 0 && coordinate.x < self.width() as i32
+		&& coordinate.y >= 0 && coordinate.y < self.height() as i32
+	}
+
+	pub fn get(&self, coordinate: &StageCoordinate) -> Option<char> {
+		if !self.contains(coordinate) { return None; }
+		self.rows[coordinate.y as usize].chars().nth(coordinate.x as usize)
+	}
+
+	fn set(&mut self, coordinate: &StageCoordinate, c: char) {
+		let row = &mut self.rows[coordinate.y as usize];
+		*row = row.chars()
+			.enumerate()
+			.map(|(x, old)| if x == coordinate.x as usize { c } else { old })
+			.collect();
+	}
+
+	fn find(&self, c: char) -> Vec<StageCoordinate> {
+		let mut found = vec![];
+		for (y, row) in self.rows.iter().enumerate() {
+			for (x, tile) in row.chars().enumerate() {
+				if tile == c { found.push(StageCoordinate::new(x as i32, y as i32)); }
+			}
+		}
+		found
+	}
+
+	// returns every tile that changed. spawn points and the snack only exist once,
+	// placing one somewhere new turns the old one into floor.
+	pub fn paint(&mut self, coordinate: &StageCoordinate, c: char) -> Vec<(StageCoordinate, char)> {
+		let mut painted = vec![];
+		if !is_known_tile(c) { return painted; }
+		match self.get(coordinate) {
+			Some(old) if old != c => {}
+			_ => return painted,
+		}
+
+		if c == '*' || spawn_point_id(c).is_some() {
+			for old in self.find(c) {
+				self.set(&old, FLOOR_TILE);
+				painted.push((old, FLOOR_TILE));
+			}
+		}
+		self.set(coordinate, c);
+		painted.push((*coordinate, c));
+		self.unsaved = true;
+		painted
+	}
+}
+
+#[derive(Component)]
+struct EditorCursor;
+
+#[derive(Component)]
+struct SpawnPointMarker {
+	snake_id: u32,
+}
+
+#[allow(clippy::type_complexity)]
+fn read_gamestate_events(
+	mut gamestate_events: EventReader<GameStateEvent>,
+	mut commands: Commands,
+	mut shared_assets: SharedAssets,
+	query: Query<Entity, Or<(With<EditorCursor>, With<SpawnPointMarker>)>>,
+) {
+	for e in gamestate_events.read() {
+		for entity in &query {
+			commands.entity(entity).despawn();
+		}
+		let GameStateData::Edit(_) = e.data else { continue; };
+
+		commands.spawn((
+			EditorCursor,
+			Mesh3d(shared_assets.cuboid(CURSOR_SIZE)),
+			MeshMaterial3d(shared_assets.custom_material("editor_cursor", || StandardMaterial {
+				base_color: CURSOR_COLOR,
+				alpha_mode: AlphaMode::Blend,
+				unlit: true,
+				..default()
+			})),
+			Transform::from_translation(HIDDEN_TRANSLATION),
+		));
+
+		// the snakes are hidden while editing, these stand in for them.
+		for snake_id in 1..=MAX_SPAWN_POINTS {
+			commands.spawn((
+				SpawnPointMarker { snake_id },
+				Mesh3d(shared_assets.cuboid(MARKER_SIZE)),
+				MeshMaterial3d(shared_assets.material(snake_color(snake_id))),
+				Transform::from_translation(HIDDEN_TRANSLATION),
+			));
+		}
+	}
+}
+
+fn move_cursor(
+	mut game_state: ResMut<GameState>,
+	mut key_events: EventReader<KeyboardInput>,
+	mut cursor_events: EventReader<CursorMoved>,
+	windows: Query<&Window, With<PrimaryWindow>>,
+	cameras: Query<(&Camera, &GlobalTransform)>,
+) {
+	let GameStateData::Edit(edit_data) = &mut game_state.data else {
+		key_events.clear();
+		cursor_events.clear();
+		return;
+	};
+
+	for e in key_events.read() {
+		if e.state != ButtonState::Pressed { continue; }
+		let (dx, dy) = match e.key_code {
+			KeyCode::ArrowUp => (0, -1),
+			KeyCode::ArrowDown => (0, 1),
+			KeyCode::ArrowLeft => (-1, 0),
+			KeyCode::ArrowRight => (1, 0),
+			_ => continue,
+		};
+		let next = StageCoordinate::new(edit_data.cursor.x + dx, edit_data.cursor.y + dy);
+		if edit_data.contains(&next) { edit_data.cursor = next; }
+	}
+
+	// mouse: cast a ray from the camera onto the tops of the tiles.
+	if cursor_events.read().last().is_none() { return; }
+	let Ok(window) = windows.single() else { return; };
+	let Some(cursor_position) = window.cursor_position() else { return; };
+	let Ok((camera, camera_transform)) = cameras.single() else { return; };
+	let Ok(ray) = camera.viewport_to_world(camera_transform, cursor_position) else { return; };
+	let Some(distance) = ray.intersect_plane(Vec3::new(0.0, CURSOR_Y, 0.0), InfinitePlane3d::new(Vec3::Y)) else { return; };
+
+	let point = ray.get_point(distance);
+	let hovered = StageCoordinate::new(point.x.round() as i32, point.z.round() as i32);
+	if edit_data.contains(&hovered) { edit_data.cursor = hovered; }
+}
+
+fn paint_tiles(
+	mut game_state: ResMut<GameState>,
+	mut key_events: EventReader<KeyboardInput>,
+	keys: Res<ButtonInput<KeyCode>>,
+	mouse: Res<ButtonInput<MouseButton>>,
+	mut stage_events: EventWriter<StageEvent>,
+	mut ui_writer: EventWriter<UIEvent>,
+) {
+	let GameStateData::Edit(edit_data) = &mut game_state.data else {
+		key_events.clear();
+		return;
+	};
+	let control = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+
+	let mut painted: Vec<(StageCoordinate, char)> = vec![];
+	let cursor = edit_data.cursor;
+
+	for e in key_events.read() {
+		if e.state != ButtonState::Pressed || control { continue; }
+		match e.key_code {
+			KeyCode::Space | KeyCode::Enter => {
+				let brush = edit_data.brush;
+				painted.extend(edit_data.paint(&cursor, brush));
+			}
+			_ => {
+				// typed text, so shift gives the upper case tiles.
+				let Some(c) = e.text.as_ref().and_then(|text| text.chars().next()) else { continue; };
+				if !is_known_tile(c) { continue; }
+				edit_data.brush = c;
+				ui_writer.write(UIEvent { id: "sub_header", text: format!("brush: {}", c) });
+				painted.extend(edit_data.paint(&cursor, c));
+			}
+		}
+	}
+
+	if mouse.pressed(MouseButton::Left) {
+		let brush = edit_data.brush;
+		painted.extend(edit_data.paint(&cursor, brush));
+	} else if mouse.pressed(MouseButton::Right) {
+		painted.extend(edit_data.paint(&cursor, VOID_TILE));
+	}
+
+	if painted.is_empty() { return; }
+	for (coordinate, c) in painted {
+		stage_events.write(StageEvent { data: StageEventData::TilePainted(coordinate, c) });
+		if c == '*' {
+			stage_events.write(StageEvent { data: StageEventData::ClearSnacks });
+			stage_events.write(StageEvent { data: StageEventData::SpawnSnack(coordinate, SnackKind::Normal) });
+		}
+	}
+	if edit_data.find('*').is_empty() {
+		stage_events.write(StageEvent { data: StageEventData::ClearSnacks });
+	}
+}
+
+fn save_layout(
+	game_state: Res<GameState>,
+	keys: Res<ButtonInput<KeyCode>>,
+	asset_server: Res<AssetServer>,
+	mut ui_writer: EventWriter<UIEvent>,
+) {
+	let GameStateData::Edit(edit_data) = &game_state.data else { return; };
+	let control = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+	if !control || !keys.just_pressed(KeyCode::KeyS) { return; }
+
+	// same rules as the loader, so a saved layout always loads again.
+	if let Err(error) = validate_layout(&edit_data.rows) {
+		println!("editor: not saving stage {}, {}", game_state.stage, error);
+		let problem = error.problems.first().map(|problem| problem.to_string()).unwrap_or_default();
+		ui_writer.write(UIEvent { id: "info", text: format!("can't save: {}", problem) });
+		return;
+	}
+
+	let path = format!("{}{}.txt", LAYOUT_FILEPATH, game_state.stage);
+	let mut text = edit_data.rows.join("\n");
+	text.push('\n');
+
+	match fs::write(asset_path(&path), text) {
+		Ok(()) => {
+			println!("editor: saved {}", path);
+			if game_state.generator.is_some() {
+				println!("editor: stage {} is generated, the saved layout is only used once it's taken out of the generator", game_state.stage);
+			}
+			// the asset server would otherwise keep handing out the layout it loaded before.
+			asset_server.reload(path.clone());
+			ui_writer.write(UIEvent { id: "info", text: format!("saved {}", path) });
+		}
+		Err(error) => {
+			println!("editor: could not save {}: {}", path, error);
+			ui_writer.write(UIEvent { id: "info", text: format!("could not save {}", path) });
+		}
+	}
+}
+
+#[allow(clippy::type_complexity)]
+fn update_cursor_and_markers(
+	mut game_state: ResMut<GameState>,
+	mut cursor_query: Query<&mut Transform, (With<EditorCursor>, Without<SpawnPointMarker>)>,
+	mut marker_query: Query<(&SpawnPointMarker, &mut Transform), Without<EditorCursor>>,
+) {
+	let GameStateData::Edit(edit_data) = &mut game_state.data else { return; };
+
+	for mut transform in &mut cursor_query {
+		transform.translation = Vec3::new(edit_data.cursor.x as f32, CURSOR_Y, edit_data.cursor.y as f32);
+	}
+
+	for (marker, mut transform) in &mut marker_query {
+		let spawn_point = char::from_digit(marker.snake_id, 10).unwrap();
+		transform.translation = match edit_data.find(spawn_point).first() {
+			Some(coordinate) => Vec3::new(coordinate.x as f32, MARKER_Y, coordinate.y as f32),
+			None => HIDDEN_TRANSLATION,
+		};
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn edit_data(layout: &str) -> EditData {
+		EditData { rows: layout.lines().map(String::from).collect(), ..EditData::new() }
+	}
+
+	#[test]
+	fn painting_a_spawn_point_moves_it() {
+		let mut data = edit_data("1AA\nA*A");
+		let painted = data.paint(&StageCoordinate::new(2, 0), '1');
+
+		assert_eq!(data.rows, vec!["AA1", "A*A"]);
+		assert_eq!(painted, vec![(StageCoordinate::new(0, 0), 'A'), (StageCoordinate::new(2, 0), '1')]);
+		assert!(data.unsaved);
+	}
+
+	#[test]
+	fn painting_ignores_unknown_tiles_and_the_outside() {
+		let mut data = edit_data("1AA\nA*A");
+
+		assert!(data.paint(&StageCoordinate::new(0, 1), '!').is_empty());
+		assert!(data.paint(&StageCoordinate::new(3, 0), 'B').is_empty());
+		assert!(data.paint(&StageCoordinate::new(1, 0), 'A').is_empty());
+		assert!(!data.unsaved);
+	}
+}
@@ -0,0 +1,196 @@
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use serde::Deserialize;
+
+use crate::layout::MAX_SPAWN_POINTS;
+
+// generator: procedural stage layouts in the same text format as the authored ones.
+// the same params (seed included) always give the same layout.
+// every spawn point is guaranteed a walkable path to the snack.
+
+const MIN_SIZE: usize = 6;
+const VOID: char = '_';
+const MAX_WALL_DENSITY: f32 = 0.6;
+const TILES_PER_HOLE: usize = 12; // at most one hole per this many inner tiles
+const WALL_ATTEMPTS_PER_TILE: usize = 4; // gives up on walls after inner tiles * this many runs
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct GeneratorParams {
+	pub seed: u64,
+	pub width: usize,
+	pub height: usize,
+	pub wall_density: f32, // share of the inner tiles turned into short void walls, 0.0 - 1.0
+	pub holes: u32, // rectangular void pits, kept to one per 12 inner tiles
+	pub players: u32, // spawn points to place, 1 - 8
+}
+
+impl GeneratorParams {
+	pub fn with_seed(&self, seed: u64) -> Self {
+		Self { seed, ..*self }
+	}
+}
+
+pub fn generate_layout(params: &GeneratorParams) -> Vec<String> {
+	let mut rng = StdRng::seed_from_u64(params.seed);
+	let width = params.width.max(MIN_SIZE);
+	let height = params.height.max(MIN_SIZE);
+	let players = params.players.clamp(1, MAX_SPAWN_POINTS);
+
+	// C border around an A floor with some B sprinkled in, like the authored stages.
+	let mut grid: Vec<Vec<char>> = vec![vec!['A'; width]; height];
+	for (y, row) in grid.iter_mut().enumerate() {
+		for (x, tile) in row.iter_mut().enumerate() {
+			if x == 0 || y == 0 || x == width - 1 || y == height - 1 {
+				*tile = 'C';
+			} else if rng.random_bool(0.12) {
+				*tile = 'B';
+			}
+		}
+	}
+
+	let inner_tiles = (width - 2) * (height - 2);
+
+	// holes: pits of 2x2 to 3x3, kept off the border.
+	let holes = (params.holes as usize).min(inner_tiles / TILES_PER_HOLE);
+	for _ in 0..holes {
+		let hole_width = rng.random_range(2..=3).min(width - 2);
+		let hole_height = rng.random_range(2..=3).min(height - 2);
+		let x0 = rng.random_range(1..=width - 1 - hole_width);
+		let y0 = rng.random_range(1..=height - 1 - hole_height);
+		for row in grid.iter_mut().skip(y0).take(hole_height) {
+			for tile in row.iter_mut().skip(x0).take(hole_width) {
+				*tile = VOID;
+			}
+		}
+	}
+
+	// walls: short straight runs of void until the density is reached, counted on what the holes left.
+	// runs keep landing on void once the stage fills up, so the attempts are capped too.
+	let open_tiles = grid[1..height - 1].iter()
+		.map(|row| row[1..width - 1].iter().filter(|tile| **tile != VOID).count())
+		.sum::<usize>();
+	let wall_tiles = (open_tiles as f32 * params.wall_density.clamp(0.0, MAX_WALL_DENSITY)) as usize;
+	let mut placed = 0;
+	for _ in 0..inner_tiles * WALL_ATTEMPTS_PER_TILE {
+		if placed >= wall_tiles { break; }
+		let length = rng.random_range(2..=4);
+		let horizontal = rng.random_bool(0.5);
+		let mut x = rng.random_range(1..width - 1);
+		let mut y = rng.random_range(1..height - 1);
+		for _ in 0..length {
+			if x >= width - 1 || y >= height - 1 { break; }
+			if grid[y][x] != VOID { placed += 1; }
+			grid[y][x] = VOID;
+			if horizontal { x += 1; } else { y += 1; }
+		}
+	}
+
+	// snack and spawn points go on distinct inner tiles. spawns sit at least one row down,
+	// snakes start out moving up and need a tile to move onto.
+	let mut candidates: Vec<(usize, usize)> = vec![];
+	for y in 2..height - 1 {
+		for x in 1..width - 1 {
+			candidates.push((x, y));
+		}
+	}
+	candidates.shuffle(&mut rng);
+
+	let (snack_x, snack_y) = candidates[0];
+	grid[snack_y][snack_x] = '*';
+
+	for (i, (x, y)) in candidates.iter().skip(1).take(players as usize).enumerate() {
+		grid[*y][*x] = char::from_digit(i as u32 + 1, 10).unwrap();
+		if grid[y - 1][*x] == VOID { grid[y - 1][*x] = 'A'; }
+		if !reachable(&grid, (*x, *y), (snack_x, snack_y)) {
+			carve_path(&mut grid, (*x, *y), (snack_x, snack_y));
+		}
+	}
+
+	grid.into_iter().map(|row| row.into_iter().collect()).collect()
+}
+
+// flood fill over non-void tiles.
+fn reachable(grid: &[Vec<char>], from: (usize, usize), to: (usize, usize)) -> bool {
+	let mut visited = vec![vec![false; grid[0].len()]; grid.len()];
+	let mut open = vec![from];
+	visited[from.1][from.0] = true;
+
+	while let Some((x, y)) = open.pop() {
+		if (x, y) == to { return true; }
+		let neighbours = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)];
+		for (nx, ny) in neighbours {
+			if ny >= grid.len() || nx >= grid[ny].len() { continue; }
+			if visited[ny][nx] || grid[ny][nx] == VOID { continue; }
+			visited[ny][nx] = true;
+			open.push((nx, ny));
+		}
+	}
+	false
+}
+
+// fill in any void along an L-shaped path, first along x, then along y.
+fn carve_path(grid: &mut [Vec<char>], from: (usize, usize), to: (usize, usize)) {
+	let (mut x, mut y) = from;
+	while (x, y) != to {
+		if x != to.0 {
+			x = if x < to.0 { x + 1 } else { x - 1 };
+		} else {
+			y = if y < to.1 { y + 1 } else { y - 1 };
+		}
+		if grid[y][x] == VOID { grid[y][x] = 'A'; }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::layout::{ spawn_point_id, validate_layout };
+
+	fn params(seed: u64) -> GeneratorParams {
+		GeneratorParams { seed, width: 16, height: 12, wall_density: 0.3, holes: 3, players: 3 }
+	}
+
+	#[test]
+	fn same_seed_gives_same_layout() {
+		assert_eq!(generate_layout(&params(7)), generate_layout(&params(7)));
+		assert_ne!(generate_layout(&params(7)), generate_layout(&params(8)));
+	}
+
+	#[test]
+	fn generated_layouts_are_valid_and_every_spawn_reaches_the_snack() {
+		// extreme params finish too, with as many spawns as asked for.
+		let extremes = [
+			GeneratorParams { seed: 0, width: 6, height: 6, wall_density: 0.6, holes: 10, players: 3 },
+			GeneratorParams { seed: 0, width: 0, height: 0, wall_density: 1.0, holes: u32::MAX, players: 3 },
+			GeneratorParams { seed: 0, width: 40, height: 7, wall_density: 5.0, holes: 500, players: 3 },
+		];
+		let all_params = (0..200).map(params)
+			.chain((0..50).flat_map(|seed| extremes.map(|params| params.with_seed(seed))));
+
+		for params in all_params {
+			let seed = params.seed;
+			let layout = generate_layout(&params);
+			if let Err(error) = validate_layout(&layout) {
+				panic!("seed {seed}: {error}\n{}", layout.join("\n"));
+			}
+
+			let grid: Vec<Vec<char>> = layout.iter().map(|row| row.chars().collect()).collect();
+			let find = |predicate: &dyn Fn(char) -> bool| -> Vec<(usize, usize)> {
+				let mut found = vec![];
+				for (y, row) in grid.iter().enumerate() {
+					for (x, c) in row.iter().enumerate() {
+						if predicate(*c) { found.push((x, y)); }
+					}
+				}
+				found
+			};
+			let snack = find(&|c| c == '*')[0];
+			let spawns = find(&|c| spawn_point_id(c).is_some());
+
+			assert_eq!(spawns.len(), 3, "seed {seed}");
+			for spawn in spawns {
+				assert!(reachable(&grid, spawn, snack), "seed {seed}: spawn {spawn:?} can't reach the snack\n{}", layout.join("\n"));
+			}
+		}
+	}
+}
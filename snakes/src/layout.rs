@@ -0,0 +1,394 @@
+use bevy::asset::{ io::Reader, AssetLoader, LoadContext };
+use bevy::prelude::*;
+use std::fmt;
+
+use crate::snake::Direction;
+use crate::stage::StageCoordinate;
+
+// layout: the stage layout text format.
+// one line per row, one character per tile:
+// A B C (or a b c) - walkable tiles in three colors
+// 1 - 8 - snake spawn points, placed on an A tile
+// * - the snack, placed on an A tile
+// x y z - portal pairs, a snake entering one comes out of the other
+// > < ^ v - conveyors, push a snake one extra tile in the arrow's direction
+// R L U D - one-way tiles, can only be entered moving right / left / up / down
+// % - crumbling tile, collapses a few moves after a snake head leaves it
+// o p q - pressure plates, each flips the doors of its own letter while a snake (head or body) is on it,
+//     but a door never closes on a snake, it waits until the snake is off it
+// O P Q - doors of the o / p / q plates, closed while their plate is free. closed doors can't be walked on
+// e f g - doors of the o / p / q plates, open while their plate is free
+// _ - - void, nothing is placed and snakes fall through
+// rows may have different lengths, layouts are padded into a rectangle with void when loaded.
+
+pub const MAX_SPAWN_POINTS: u32 = 8;
+
+// stage layouts are loaded through the asset server,
+// so they resolve against the asset root and hot reload while the game runs.
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct StageLayout {
+	pub rows: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct StageLayoutLoader;
+
+#[derive(Debug)]
+pub enum StageLayoutLoaderError {
+	Io(std::io::Error),
+	Utf8(std::str::Utf8Error),
+}
+
+impl fmt::Display for StageLayoutLoaderError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			StageLayoutLoaderError::Io(error) => write!(f, "could not read layout: {error}"),
+			StageLayoutLoaderError::Utf8(error) => write!(f, "layout is not valid utf-8: {error}"),
+		}
+	}
+}
+
+impl std::error::Error for StageLayoutLoaderError {}
+
+impl From<std::io::Error> for StageLayoutLoaderError {
+	fn from(error: std::io::Error) -> Self { StageLayoutLoaderError::Io(error) }
+}
+
+impl AssetLoader for StageLayoutLoader {
+	type Asset = StageLayout;
+	type Settings = ();
+	type Error = StageLayoutLoaderError;
+
+	async fn load(
+		&self,
+		reader: &mut dyn Reader,
+		_settings: &(),
+		_load_context: &mut LoadContext<'_>,
+	) -> Result<Self::Asset, Self::Error> {
+		let mut bytes: Vec<u8> = vec![];
+		reader.read_to_end(&mut bytes).await?;
+		let text = std::str::from_utf8(&bytes).map_err(StageLayoutLoaderError::Utf8)?;
+
+		// validation is up to the stage, so an invalid layout can be shown instead of just failing to load.
+		let rows = normalise_layout(parse_layout(text));
+		Ok(StageLayout { rows })
+	}
+
+	fn extensions(&self) -> &[&str] {
+		&["txt"]
+	}
+}
+
+pub fn parse_layout(text: &str) -> Vec<String> {
+	text.lines().map(String::from).collect()
+}
+
+// pad every row with void up to the widest one, and drop empty rows at the end of the file,
+// so the stage is always a full rectangle.
+pub fn normalise_layout(mut layout: Vec<String>) -> Vec<String> {
+	while layout.last().is_some_and(|line| line.is_empty()) {
+		layout.pop();
+	}
+
+	let width = layout.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+	for line in &mut layout {
+		let padding = width - line.chars().count();
+		line.extend(std::iter::repeat_n('_', padding));
+	}
+	layout
+}
+
+pub fn is_void_tile(c: char) -> bool {
+	matches!(c, '_' | '-')
+}
+
+pub fn spawn_point_id(c: char) -> Option<u32> {
+	c.to_digit(10).filter(|id| *id >= 1 && *id <= MAX_SPAWN_POINTS)
+}
+
+pub const PORTAL_TILES: [char; 3] = ['x', 'y', 'z'];
+pub const CRUMBLING_TILE: char = '%';
+pub const PLATE_TILES: [char; 3] = ['o', 'p', 'q'];
+pub const DOOR_CLOSED_TILES: [char; 3] = ['O', 'P', 'Q']; // by plate index
+pub const DOOR_OPEN_TILES: [char; 3] = ['e', 'f', 'g']; // by plate index
+
+pub fn portal_index(c: char) -> Option<usize> {
+	PORTAL_TILES.iter().position(|portal| *portal == c)
+}
+
+pub fn plate_index(c: char) -> Option<usize> {
+	PLATE_TILES.iter().position(|plate| *plate == c)
+}
+
+// the index of the plate a door belongs to, and whether it's open while that plate is free.
+pub fn door_link(c: char) -> Option<(usize, bool)> {
+	DOOR_CLOSED_TILES.iter().position(|door| *door == c).map(|plate| (plate, false))
+		.or_else(|| DOOR_OPEN_TILES.iter().position(|door| *door == c).map(|plate| (plate, true)))
+}
+
+pub fn conveyor_direction(c: char) -> Option<Direction> {
+	match c {
+		'^' => Some(Direction::Up),
+		'v' => Some(Direction::Down),
+		'<' => Some(Direction::Left),
+		'>' => Some(Direction::Right),
+		_ => None,
+	}
+}
+
+pub fn one_way_direction(c: char) -> Option<Direction> {
+	match c {
+		'U' => Some(Direction::Up),
+		'D' => Some(Direction::Down),
+		'L' => Some(Direction::Left),
+		'R' => Some(Direction::Right),
+		_ => None,
+	}
+}
+
+pub fn is_known_tile(c: char) -> bool {
+	matches!(c, 'A' | 'a' | 'B' | 'b' | 'C' | 'c' | '*' | CRUMBLING_TILE) 
+	|| is_void_tile(c) 
+	|| spawn_point_id(c).is_some() 
+	|| portal_index(c).is_some()
+	|| plate_index(c).is_some()
+	|| door_link(c).is_some()
+	|| conveyor_direction(c).is_some()
+	|| one_way_direction(c).is_some()
+}
+
+// both ends of every portal pair in the layout. assumes a valid layout.
+pub fn find_portal_pairs(layout: &[String]) -> Vec<(StageCoordinate, StageCoordinate)> {
+	let mut ends: Vec<Vec<StageCoordinate>> = vec![vec![]; PORTAL_TILES.len()];
+	for (y, line) in layout.iter().enumerate() {
+		for (x, c) in line.chars().enumerate() {
+			if let Some(i) = portal_index(c) {
+				ends[i].push(StageCoordinate::new(x as i32, y as i32));
+			}
+		}
+	}
+
+	ends.into_iter()
+		.filter(|pair| pair.len() == 2)
+		.map(|pair| (pair[0], pair[1]))
+		.collect()
+}
+
+// rows and columns are 1-indexed in problems, so they match what a text editor shows.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayoutProblem {
+	Empty,
+	UnknownTile { row: usize, column: usize, tile: char },
+	DuplicateSpawnPoint { row: usize, column: usize, snake_id: u32 },
+	MissingSpawnPoint { snake_id: u32 },
+	DuplicateSnack { row: usize, column: usize },
+	MissingSnack,
+	UnpairedPortal { row: usize, column: usize, tile: char },
+	ExtraPortal { row: usize, column: usize, tile: char },
+	DoorWithoutPlate { row: usize, column: usize, tile: char },
+	PlateWithoutDoor { row: usize, column: usize, tile: char },
+}
+
+impl fmt::Display for LayoutProblem {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			LayoutProblem::Empty => write!(f, "layout is empty"),
+			LayoutProblem::UnknownTile { row, column, tile } => write!(f, "row {row}, column {column}: unknown tile '{tile}'"),
+			LayoutProblem::DuplicateSpawnPoint { row, column, snake_id } => write!(f, "row {row}, column {column}: spawn point {snake_id} is already placed"),
+			LayoutProblem::MissingSpawnPoint { snake_id } => write!(f, "spawn point {snake_id} is missing"),
+			LayoutProblem::DuplicateSnack { row, column } => write!(f, "row {row}, column {column}: snack is already placed"),
+			LayoutProblem::MissingSnack => write!(f, "snack is missing"),
+			LayoutProblem::UnpairedPortal { row, column, tile } => write!(f, "row {row}, column {column}: portal '{tile}' has no partner"),
+			LayoutProblem::ExtraPortal { row, column, tile } => write!(f, "row {row}, column {column}: portal '{tile}' already has two ends"),
+			LayoutProblem::DoorWithoutPlate { row, column, tile } => write!(f, "row {row}, column {column}: door '{tile}' can never move, there is no plate for it"),
+			LayoutProblem::PlateWithoutDoor { row, column, tile } => write!(f, "row {row}, column {column}: plate '{tile}' has no doors"),
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayoutError {
+	pub problems: Vec<LayoutProblem>,
+}
+
+impl fmt::Display for LayoutError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{} layout problem(s)", self.problems.len())?;
+		for problem in &self.problems {
+			write!(f, "\n  {problem}")?;
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for LayoutError {}
+
+// collect every problem instead of stopping at the first one,
+// so a designer can fix a stage in one go.
+pub fn validate_layout(layout: &[String]) -> Result<(), LayoutError> {
+	let mut problems: Vec<LayoutProblem> = vec![];
+
+	if layout.iter().all(|line| line.is_empty()) {
+		problems.push(LayoutProblem::Empty);
+		return Err(LayoutError { problems });
+	}
+
+	let mut spawn_points_found: Vec<u32> = vec![];
+	let mut snack_found = false;
+	let mut portals_found: Vec<Vec<(usize, usize)>> = vec![vec![]; PORTAL_TILES.len()];
+	let mut plates_found: Vec<Vec<(usize, usize)>> = vec![vec![]; PLATE_TILES.len()];
+	let mut doors_found: Vec<Vec<(usize, usize, char)>> = vec![vec![]; PLATE_TILES.len()];
+
+	for (y, line) in layout.iter().enumerate() {
+		for (x, c) in line.chars().enumerate() {
+			let (row, column) = (y + 1, x + 1);
+
+			if !is_known_tile(c) {
+				problems.push(LayoutProblem::UnknownTile { row, column, tile: c });
+			} else if let Some(snake_id) = spawn_point_id(c) {
+				if spawn_points_found.contains(&snake_id) {
+					problems.push(LayoutProblem::DuplicateSpawnPoint { row, column, snake_id });
+				} else {
+					spawn_points_found.push(snake_id);
+				}
+			} else if c == '*' {
+				if snack_found {
+					problems.push(LayoutProblem::DuplicateSnack { row, column });
+				}
+				snack_found = true;
+			} else if let Some(i) = portal_index(c) {
+				if portals_found[i].len() >= 2 {
+					problems.push(LayoutProblem::ExtraPortal { row, column, tile: c });
+				} else {
+					portals_found[i].push((row, column));
+				}
+			} else if let Some(i) = plate_index(c) {
+				plates_found[i].push((row, column));
+			} else if let Some((i, _open)) = door_link(c) {
+				doors_found[i].push((row, column, c));
+			}
+		}
+	}
+
+	for (i, ends) in portals_found.iter().enumerate() {
+		if let [(row, column)] = ends[..] {
+			problems.push(LayoutProblem::UnpairedPortal { row, column, tile: PORTAL_TILES[i] });
+		}
+	}
+
+	// a plate may have more than one tile, any of them holds its doors.
+	for (i, (plates, doors)) in plates_found.iter().zip(&doors_found).enumerate() {
+		if doors.is_empty() {
+			for (row, column) in plates {
+				problems.push(LayoutProblem::PlateWithoutDoor { row: *row, column: *column, tile: PLATE_TILES[i] });
+			}
+		}
+		if plates.is_empty() {
+			for (row, column, tile) in doors {
+				problems.push(LayoutProblem::DoorWithoutPlate { row: *row, column: *column, tile: *tile });
+			}
+		}
+	}
+
+	// player 1 is always active, the other snakes join on input and may be left out.
+	if !spawn_points_found.contains(&1) {
+		problems.push(LayoutProblem::MissingSpawnPoint { snake_id: 1 });
+	}
+	if !snack_found {
+		problems.push(LayoutProblem::MissingSnack);
+	}
+
+	if problems.is_empty() { Ok(()) } else { Err(LayoutError { problems }) }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::fs;
+
+	const STAGE_LAYOUTS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/stage_layouts");
+
+	#[test]
+	fn all_stage_layouts_are_valid() {
+		let mut failures: Vec<String> = vec![];
+		let mut checked = 0;
+
+		for entry in fs::read_dir(STAGE_LAYOUTS_DIR).expect("stage layouts dir not found") {
+			let path = entry.unwrap().path();
+			if path.extension().is_none_or(|extension| extension != "txt") { continue; }
+
+			let text = fs::read_to_string(&path).unwrap();
+			if let Err(error) = validate_layout(&normalise_layout(parse_layout(&text))) {
+				failures.push(format!("{}: {error}", path.display()));
+			}
+			checked += 1;
+		}
+
+		assert!(checked > 0, "no stage layouts found in {STAGE_LAYOUTS_DIR}");
+		assert!(failures.is_empty(), "{}", failures.join("\n"));
+	}
+
+	#[test]
+	fn problems_are_reported_with_row_and_column() {
+		let layout = parse_layout("AAA\nA?*\n*2A\nA2A");
+		let error = validate_layout(&layout).unwrap_err();
+
+		assert_eq!(error.problems, vec![
+			LayoutProblem::UnknownTile { row: 2, column: 2, tile: '?' },
+			LayoutProblem::DuplicateSnack { row: 3, column: 1 },
+			LayoutProblem::DuplicateSpawnPoint { row: 4, column: 2, snake_id: 2 },
+			LayoutProblem::MissingSpawnPoint { snake_id: 1 },
+		]);
+	}
+
+	#[test]
+	fn portals_must_come_in_pairs() {
+		let layout = parse_layout("1x*y\nAxAx\nAAAA");
+		let error = validate_layout(&layout).unwrap_err();
+
+		assert_eq!(error.problems, vec![
+			LayoutProblem::ExtraPortal { row: 2, column: 4, tile: 'x' },
+			LayoutProblem::UnpairedPortal { row: 1, column: 4, tile: 'y' },
+		]);
+	}
+
+	#[test]
+	fn plates_and_doors_need_each_other() {
+		let error = validate_layout(&parse_layout("1O*e")).unwrap_err();
+		assert_eq!(error.problems, vec![
+			LayoutProblem::DoorWithoutPlate { row: 1, column: 2, tile: 'O' },
+			LayoutProblem::DoorWithoutPlate { row: 1, column: 4, tile: 'e' },
+		]);
+
+		// the p plate has no doors, and the o doors don't answer to it.
+		let error = validate_layout(&parse_layout("1O*p")).unwrap_err();
+		assert_eq!(error.problems, vec![
+			LayoutProblem::DoorWithoutPlate { row: 1, column: 2, tile: 'O' },
+			LayoutProblem::PlateWithoutDoor { row: 1, column: 4, tile: 'p' },
+		]);
+
+		assert!(validate_layout(&parse_layout("1O*o\nApPf")).is_ok());
+	}
+
+	#[test]
+	fn portal_pairs_are_found() {
+		let layout = parse_layout("1x*z\nAxzA");
+		let pairs = find_portal_pairs(&layout);
+
+		assert_eq!(pairs.len(), 2);
+		assert!(pairs[0].0.equals(&StageCoordinate::new(1, 0)) && pairs[0].1.equals(&StageCoordinate::new(1, 1)));
+		assert!(pairs[1].0.equals(&StageCoordinate::new(3, 0)) && pairs[1].1.equals(&StageCoordinate::new(2, 1)));
+	}
+
+	#[test]
+	fn ragged_layouts_are_padded_into_a_rectangle() {
+		let layout = normalise_layout(parse_layout("AA*\nA1\n\nAAAA\n\n"));
+		assert_eq!(layout, vec!["AA*_", "A1__", "____", "AAAA"]);
+	}
+
+	#[test]
+	fn empty_layout_is_invalid() {
+		let error = validate_layout(&parse_layout("\n\n")).unwrap_err();
+		assert_eq!(error.problems, vec![LayoutProblem::Empty]);
+	}
+}
@@ -1,4 +1,14 @@
+mod actions;
 mod anim;
+mod campaign;
+mod editor;
+mod generator;
+mod layout;
+mod metadata;
+mod players;
+mod rebind;
+mod rng;
+mod shared_assets;
 mod snacks;
 mod snake;
 mod stage;
@@ -6,7 +16,12 @@ mod state;
 mod ui;
 
 use bevy::prelude::*;
+use actions::ActionsPlugin;
 use anim::AnimPlugin;
+use editor::EditorPlugin;
+use rebind::RebindPlugin;
+use rng::RngPlugin;
+use shared_assets::SharedAssetsPlugin;
 use snacks::SnacksPlugin;
 use snake::SnakePlugin;
 use stage::StagePlugin;
@@ -21,7 +36,7 @@ fn main() {
 			brightness: 450.0,
 			affects_lightmapped_meshes: false,
 		})
-		.add_plugins((StatePlugin, SnakePlugin, StagePlugin, SnacksPlugin, UIPlugin, AnimPlugin))
-		.add_plugins(DefaultPlugins)
+		.add_plugins(DefaultPlugins) // first - the stage plugin registers its layout asset with the asset server
+		.add_plugins((RngPlugin, ActionsPlugin, RebindPlugin, SharedAssetsPlugin, StatePlugin, SnakePlugin, StagePlugin, SnacksPlugin, UIPlugin, AnimPlugin, EditorPlugin))
 		.run();
 }
@@ -0,0 +1,171 @@
+use serde::Deserialize;
+use std::fs;
+
+use crate::state::asset_path;
+
+// stage metadata: optional per-stage settings read from a RON sidecar
+// next to the layout, e.g. stage_3.ron next to stage_3.txt.
+// every field is optional - anything left out falls back to the
+// hard-coded defaults in state.rs and stage.rs.
+// a title in the campaign manifest is shown instead of the name.
+//
+// (
+//     name: "the ring",
+//     goal: 12,
+//     start_speed: 3.0,
+//     speed_increment: 0.1,
+//     spotlight_translation: (6.0, 8.0, 4.0),
+//     spotlight_intensity_multiplier: 1.0,
+//     wrap_edges: true,
+//     reveal: Spiral,
+//     snack_count: 3,
+//     snack_placement: Fair(2),
+//     snack_lifetime: 8.0,
+//     snack_countdown: true,
+//     snack_weights: (normal: 10, golden: 1, slow_down: 1, shrink: 1, bonus_time: 1),
+//     time_limit: 90.0,
+//     colors: (
+//         tiles_a: (120, 120, 120),
+//         clear_color: (15, 15, 20),
+//     ),
+// )
+
+const METADATA_FILEPATH: &str = "stage_layouts/stage_";
+
+#[derive(Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct StageMetadata {
+	pub name: Option<String>,
+	pub goal: Option<u32>,
+	pub start_speed: Option<f32>,
+	pub speed_increment: Option<f32>,
+	pub spotlight_translation: Option<(f32, f32, f32)>,
+	pub spotlight_intensity_multiplier: Option<f32>,
+	pub wrap_edges: Option<bool>, // leaving the grid comes back in on the other side, instead of falling
+	pub reveal: Option<RevealPattern>, // order the tiles are placed in while the stage is set
+	pub snack_count: Option<u32>, // snacks on the board at once, 1 if left out
+	pub snack_placement: Option<SnackPlacement>, // overrides the campaign's, Random if neither has one
+	pub snack_lifetime: Option<f32>, // seconds before an uneaten snack moves somewhere else, never if left out
+	pub snack_countdown: Option<bool>, // show the seconds a snack has left next to it
+	pub snack_weights: SnackWeightsMetadata,
+	pub time_limit: Option<f32>, // seconds, running out is a death
+	pub colors: StageColorsMetadata,
+}
+
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+pub enum RevealPattern {
+	#[default]
+	Rows, // left to right, top to bottom
+	Spiral, // outwards from the center
+	Scatter, // random order
+	Diagonal, // sweeping from the top left corner
+	Drop, // row by row, each tile falling into place from above
+}
+
+// how a free tile is picked for a new snack. both only pick tiles a snake can get to.
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+pub enum SnackPlacement {
+	#[default]
+	Random, // any of them
+	Fair(u32), // the heads' distances to the tile, in moves, differ by at most this much
+}
+
+// relative chances for each snack kind, left out means 0 - or 1 for normal.
+// bonus time snacks only show up on stages with a time limit.
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct SnackWeightsMetadata {
+	pub normal: Option<u32>,
+	pub golden: Option<u32>,
+	pub slow_down: Option<u32>,
+	pub shrink: Option<u32>,
+	pub bonus_time: Option<u32>,
+}
+
+// colors are srgb bytes, same as the hard-coded tile colors.
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct StageColorsMetadata {
+	pub tiles_a: Option<(u8, u8, u8)>,
+	pub tiles_b: Option<(u8, u8, u8)>,
+	pub tiles_c: Option<(u8, u8, u8)>,
+	pub clear_color: Option<(u8, u8, u8)>,
+}
+
+impl StageMetadata {
+	pub fn load(stage_id: u32) -> Self {
+		let path = format!("{}{}.ron", METADATA_FILEPATH, stage_id);
+
+		let Ok(text) = fs::read_to_string(asset_path(&path)) else {
+			println!("stage metadata: no metadata for stage {}, using defaults", stage_id);
+			return Self::default();
+		};
+
+		match Self::parse(&text) {
+			Ok(metadata) => metadata,
+			Err(error) => {
+				println!("stage metadata: could not read {}, using defaults: {}", path, error);
+				Self::default()
+			}
+		}
+	}
+
+	pub fn parse(text: &str) -> Result<Self, ron::error::SpannedError> {
+		// implicit_some lets the sidecar say `goal: 12` instead of `goal: Some(12)`.
+		ron::Options::default()
+			.with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+			.from_str(text)
+	}
+
+	pub fn display_name(&self, stage_id: u32, campaign_title: Option<&str>) -> String {
+		match campaign_title.or(self.name.as_deref()) {
+			Some(name) => format!("stage {}: {}", stage_id, name),
+			None => format!("stage {}", stage_id),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const STAGE_LAYOUTS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/stage_layouts");
+
+	#[test]
+	fn all_stage_metadata_files_parse() {
+		let mut failures: Vec<String> = vec![];
+
+		for entry in fs::read_dir(STAGE_LAYOUTS_DIR).expect("stage layouts dir not found") {
+			let path = entry.unwrap().path();
+			if path.extension().is_none_or(|extension| extension != "ron") { continue; }
+
+			let text = fs::read_to_string(&path).unwrap();
+			if let Err(error) = StageMetadata::parse(&text) {
+				failures.push(format!("{}: {error}", path.display()));
+			}
+		}
+
+		assert!(failures.is_empty(), "{}", failures.join("\n"));
+	}
+
+	#[test]
+	fn campaign_titles_win_over_names() {
+		let metadata = StageMetadata::parse("(name: \"the ring\")").unwrap();
+
+		assert_eq!(metadata.display_name(3, None), "stage 3: the ring");
+		assert_eq!(metadata.display_name(3, Some("ring two")), "stage 3: ring two");
+		assert_eq!(StageMetadata::default().display_name(4, None), "stage 4");
+	}
+
+	#[test]
+	fn missing_fields_fall_back_to_none() {
+		let metadata = StageMetadata::parse("(goal: 3, reveal: Drop, colors: (tiles_b: (1, 2, 3)))").unwrap();
+
+		assert_eq!(metadata.goal, Some(3));
+		assert_eq!(metadata.reveal, Some(RevealPattern::Drop));
+		assert_eq!(metadata.wrap_edges, None);
+		assert_eq!(metadata.start_speed, None);
+		assert_eq!(metadata.colors.tiles_b, Some((1, 2, 3)));
+		assert_eq!(metadata.colors.tiles_a, None);
+	}
+}
@@ -0,0 +1,83 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::fs;
+
+use crate::layout::MAX_SPAWN_POINTS;
+use crate::state::asset_path;
+
+// players: how many snakes there are, read from a RON file at startup.
+// snake 1 always plays, the others join by pressing one of their keys before the stage starts.
+// a snake can only join on stages that have its spawn point.
+// snakes past the ones with keys in the controls need a gamepad to join, and get one first.
+//
+// (
+//     count: 4,
+// )
+
+const PLAYERS_PATH: &str = "players.ron";
+const DEFAULT_COUNT: u32 = 3;
+
+#[derive(Resource, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PlayersConfig {
+	pub count: u32, // 1 - MAX_SPAWN_POINTS
+}
+
+impl Default for PlayersConfig {
+	fn default() -> Self {
+		Self { count: DEFAULT_COUNT }
+	}
+}
+
+impl PlayersConfig {
+	pub fn load() -> Self {
+		let config = match fs::read_to_string(asset_path(PLAYERS_PATH)) {
+			Ok(text) => match Self::parse(&text) {
+				Ok(config) => config,
+				Err(error) => {
+					println!("players: could not read {}, using defaults: {}", PLAYERS_PATH, error);
+					Self::default()
+				}
+			},
+			Err(_) => {
+				println!("players: no {} found, using defaults", PLAYERS_PATH);
+				Self::default()
+			}
+		};
+		config.clamped()
+	}
+
+	pub fn parse(text: &str) -> Result<Self, ron::error::SpannedError> {
+		ron::from_str(text)
+	}
+
+	fn clamped(self) -> Self {
+		let count = self.count.clamp(1, MAX_SPAWN_POINTS);
+		if count != self.count {
+			println!("players: {} players asked for, using {}", self.count, count);
+		}
+		Self { count }
+	}
+
+	pub fn ids(&self) -> impl Iterator<Item = u32> {
+		1..=self.count
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn players_file_parses() {
+		let path = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/players.ron");
+		let config = PlayersConfig::parse(&fs::read_to_string(path).unwrap()).unwrap();
+		assert_eq!(config, config.clamped());
+	}
+
+	#[test]
+	fn player_count_is_kept_to_the_spawn_points() {
+		assert_eq!(PlayersConfig { count: 0 }.clamped().count, 1);
+		assert_eq!(PlayersConfig { count: 20 }.clamped().count, MAX_SPAWN_POINTS);
+		assert_eq!(PlayersConfig { count: 5 }.clamped().ids().collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+	}
+}
@@ -0,0 +1,260 @@
+use bevy::input::keyboard::KeyboardInput;
+use bevy::prelude::*;
+
+use crate::actions::{ direction_name, key_name, Controls, SnakeKeys, CONTROLS_KEY, EDIT_STAGE_KEY };
+use crate::players::PlayersConfig;
+use crate::snake::Direction;
+use crate::state::{ GameState, GameStateData, GameStateEvent };
+use crate::ui::UIEvent;
+
+// rebind plugin: the controls screen, reached from the start screen.
+// works on a copy of the controls, applied and saved when leaving. there's no leaving while
+// two bindings share a key, so the controls in use never have a conflict.
+// also lists the snakes' keys on the start screen.
+// the keys here are fixed, so a bad binding can always be undone:
+//
+// up / down: pick a binding
+// enter: press the new key for it - esc while waiting: keep the old one
+// esc: save and go back, once no two bindings share a key
+
+const DIRECTIONS: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+
+pub struct RebindPlugin;
+
+impl Plugin for RebindPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_systems(Update, (read_keys, show_start_info));
+	}
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RebindData {
+	controls: Option<Controls>, // working copy, taken when the screen opens
+	selected: usize,
+	waiting: bool, // for the key to bind
+	pub done: bool,
+}
+
+impl RebindData {
+	pub fn new() -> Self {
+		Self::default()
+	}
+}
+
+// the snakes that are playing, the only ones shown and checked for conflicts.
+fn shown_snakes(controls: &Controls, players: &PlayersConfig) -> usize {
+	controls.snakes.len().min(players.count as usize)
+}
+
+// one line per snake direction, for the shown snakes that have keys.
+fn bindings(controls: &Controls, players: &PlayersConfig) -> Vec<(usize, Direction)> {
+	(0..shown_snakes(controls, players))
+		.filter(|i| controls.snakes[*i].is_some())
+		.flat_map(|i| DIRECTIONS.map(|direction| (i, direction)))
+		.collect()
+}
+
+fn list_text(controls: &Controls, players: &PlayersConfig, selected: usize, waiting: bool) -> String {
+	let conflicts = controls.conflicts(shown_snakes(controls, players));
+	let mut lines: Vec<String> = vec![];
+
+	for (line, (i, direction)) in bindings(controls, players).into_iter().enumerate() {
+		let selected = line == selected;
+		let key = match controls.snakes[i].and_then(|keys| keys.get(direction)) {
+			_ if selected && waiting => String::from("press a key..."),
+			Some(key_code) => key_name(key_code),
+			None => String::from("-"),
+		};
+		let shared = conflicts.iter().any(|conflict| conflict.starts_with(&format!("{key}:")));
+		lines.push(format!("{} snake {} {}: {}{}",
+			if selected { ">" } else { " " },
+			i + 1,
+			direction_name(direction),
+			key,
+			if shared { "  (shared!)" } else { "" },
+		));
+	}
+
+	// the start screen keys can't be rebound, but they're shown so nothing gets bound over them.
+	for (name, key_code) in [("edit stage", EDIT_STAGE_KEY), ("controls", CONTROLS_KEY)] {
+		lines.push(format!("  {}: {} (fixed)", name, key_name(key_code)));
+	}
+
+	if !conflicts.is_empty() {
+		lines.push(String::new());
+		lines.push(String::from("shared keys, change them to save and leave:"));
+		lines.extend(conflicts);
+	}
+	lines.join("\n")
+}
+
+// key names as printed on the key, e.g. "W" for KeyW.
+fn short_key_name(key_code: KeyCode) -> String {
+	let name = key_name(key_code);
+	match name.strip_prefix("Key").or(name.strip_prefix("Digit")) {
+		Some(short) => short.to_string(),
+		None => name.replacen("Numpad", "num", 1),
+	}
+}
+
+// the keys of one snake the way they sit on the keyboard - up, left, down, right - e.g. "WASD".
+fn keys_text(keys: &SnakeKeys) -> String {
+	let all = [keys.up, keys.left, keys.down, keys.right];
+	if all == [KeyCode::ArrowUp, KeyCode::ArrowLeft, KeyCode::ArrowDown, KeyCode::ArrowRight] {
+		return String::from("arrows");
+	}
+	let names: Vec<String> = all.iter().map(|key_code| short_key_name(*key_code)).collect();
+	if names.iter().all(|name| name.chars().count() == 1) { names.concat() } else { names.join("/") }
+}
+
+fn start_info(controls: &Controls, players: &PlayersConfig) -> String {
+	let keys: Vec<String> = controls.snakes.iter().take(shown_snakes(controls, players)).flatten().map(keys_text).collect();
+	let keys = if keys.is_empty() { String::new() } else { format!("keys: {} - ", keys.join(", ")) };
+	format!("{}gamepads: d-pad or stick - {}: controls - {}: edit stage", keys, short_key_name(CONTROLS_KEY), short_key_name(EDIT_STAGE_KEY))
+}
+
+fn show_start_info(
+	mut gamestate_events: EventReader<GameStateEvent>,
+	mut ui_writer: EventWriter<UIEvent>,
+	controls: Res<Controls>,
+	players: Res<PlayersConfig>,
+) {
+	for e in gamestate_events.read() {
+		let GameStateData::Start = e.data else { continue; };
+		ui_writer.write(UIEvent { id: "info", text: start_info(&controls, &players) });
+	}
+}
+
+fn read_keys(
+	mut game_state: ResMut<GameState>,
+	mut key_events: EventReader<KeyboardInput>,
+	mut controls: ResMut<Controls>,
+	mut ui_writer: EventWriter<UIEvent>,
+	players: Res<PlayersConfig>,
+) {
+	let GameStateData::Rebind(rebind_data) = &mut game_state.data else {
+		key_events.clear();
+		return;
+	};
+	if rebind_data.done { return; }
+
+	let mut changed = rebind_data.controls.is_none();
+	let working = rebind_data.controls.get_or_insert_with(|| controls.clone());
+	let lines = bindings(working, &players);
+
+	for e in key_events.read().filter(|e| e.state.is_pressed() && !e.repeat) {
+		changed = true;
+		if rebind_data.waiting {
+			rebind_data.waiting = false;
+			if e.key_code == KeyCode::Escape { continue; }
+			if let Some((i, direction)) = lines.get(rebind_data.selected)
+			&& let Some(keys) = &mut working.snakes[*i] {
+				keys.set(*direction, e.key_code);
+			}
+			continue;
+		}
+
+		match e.key_code {
+			KeyCode::ArrowUp => rebind_data.selected = rebind_data.selected.saturating_sub(1),
+			KeyCode::ArrowDown => rebind_data.selected = (rebind_data.selected + 1).min(lines.len().saturating_sub(1)),
+			KeyCode::Enter if !lines.is_empty() => rebind_data.waiting = true,
+			KeyCode::Escape => {
+				// the list already says what's shared.
+				if !working.conflicts(shown_snakes(working, &players)).is_empty() { continue; }
+				*controls = working.clone();
+				if let Err(error) = controls.save() {
+					println!("rebind: could not save controls: {}", error);
+				}
+				rebind_data.done = true;
+				ui_writer.write(UIEvent { id: "list", text: String::new() });
+				return;
+			}
+			_ => {}
+		}
+	}
+
+	if changed {
+		ui_writer.write(UIEvent { id: "list", text: list_text(working, &players, rebind_data.selected, rebind_data.waiting) });
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn list_marks_shared_keys() {
+		let players = PlayersConfig { count: 2 };
+		let mut controls = Controls::default();
+		assert!(!list_text(&controls, &players, 0, false).contains("shared"));
+		assert_eq!(bindings(&controls, &players).len(), 8);
+
+		controls.snakes[1].as_mut().unwrap().set(Direction::Up, KeyCode::ArrowUp);
+		let text = list_text(&controls, &players, 0, false);
+		assert!(text.contains("> snake 1 up: ArrowUp  (shared!)"));
+		assert!(text.contains("ArrowUp: snake 1 up, snake 2 up"));
+		assert!(text.contains("edit stage: KeyE (fixed)"));
+
+		// the start screen keys are taken too.
+		let mut controls = Controls::default();
+		controls.snakes[0].as_mut().unwrap().set(Direction::Up, KeyCode::KeyE);
+		controls.snakes[1].as_mut().unwrap().set(Direction::Down, KeyCode::KeyC);
+		let text = list_text(&controls, &players, 0, false);
+		assert!(text.contains("> snake 1 up: KeyE  (shared!)"));
+		assert!(text.contains("KeyE: snake 1 up, edit stage"));
+		assert!(text.contains("KeyC: snake 2 down, controls"));
+	}
+
+	#[test]
+	fn start_info_lists_the_playing_snakes_keys() {
+		let mut controls = Controls::default();
+		assert_eq!(start_info(&controls, &PlayersConfig { count: 3 }), "keys: arrows, WASD, IJKL - gamepads: d-pad or stick - C: controls - E: edit stage");
+		assert!(start_info(&controls, &PlayersConfig { count: 6 }).contains("num8/num4/num5/num6, TFGH, Home/Delete/End/PageDown"));
+
+		controls.snakes[0].as_mut().unwrap().set(Direction::Up, KeyCode::Digit1);
+		assert!(start_info(&controls, &PlayersConfig { count: 1 }).starts_with("keys: 1/ArrowLeft/ArrowDown/ArrowRight -"));
+		controls.snakes.clear();
+		assert!(start_info(&controls, &PlayersConfig { count: 1 }).starts_with("gamepads"));
+	}
+
+	#[test]
+	fn shared_keys_keep_the_screen_open() {
+		use bevy::ecs::system::RunSystemOnce;
+		use bevy::input::{ ButtonState, keyboard::Key };
+
+		let mut world = World::new();
+		world.init_resource::<Events<KeyboardInput>>();
+		world.init_resource::<Events<UIEvent>>();
+		world.insert_resource(PlayersConfig { count: 2 });
+		world.insert_resource(Controls::default());
+		let mut working = Controls::default();
+		working.snakes[1].as_mut().unwrap().set(Direction::Up, KeyCode::ArrowUp);
+		world.insert_resource(GameState {
+			data: GameStateData::Rebind(RebindData { controls: Some(working.clone()), ..default() }),
+			..default()
+		});
+
+		world.send_event(KeyboardInput {
+			key_code: KeyCode::Escape,
+			logical_key: Key::Escape,
+			state: ButtonState::Pressed,
+			text: None,
+			repeat: false,
+			window: Entity::PLACEHOLDER,
+		});
+		world.run_system_once(read_keys).unwrap();
+
+		let GameStateData::Rebind(rebind_data) = &world.resource::<GameState>().data else { panic!("left the controls screen"); };
+		assert!(!rebind_data.done);
+		assert_eq!(rebind_data.controls, Some(working));
+		assert_eq!(*world.resource::<Controls>(), Controls::default());
+	}
+
+	#[test]
+	fn snakes_that_are_not_shown_do_not_conflict() {
+		let players = PlayersConfig { count: 2 };
+		let mut controls = Controls::default();
+		controls.snakes[4].as_mut().unwrap().set(Direction::Up, KeyCode::ArrowUp);
+		assert!(!list_text(&controls, &players, 0, false).contains("shared"));
+	}
+}
@@ -0,0 +1,119 @@
+use bevy::prelude::*;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::state::{ GameStateData, GameStateEvent };
+use crate::ui::UIEvent;
+
+// rng plugin: every random decision goes through GameRng, so a run can be played back from its seed.
+// gameplay (snack placement and kinds) and visuals (reveal order, tumbling tiles) draw from
+// separate streams - how many tiles tumble can't change where the next snack lands.
+// both streams restart from the seed at the start of every stage.
+// the seed comes from `--seed <n>` on the command line, or is picked at random,
+// and is shown on the death screen.
+
+pub struct RngPlugin;
+
+impl Plugin for RngPlugin {
+	fn build(&self, app: &mut App) {
+		let seed = seed_from_args(std::env::args()).unwrap_or_else(rand::random);
+		println!("rng: seed {}", seed);
+		app.insert_resource(GameRng::new(seed));
+		app.add_systems(Update, show_seed_on_death);
+	}
+}
+
+const GAMEPLAY_STREAM: u64 = 0;
+const VISUAL_STREAM: u64 = 1;
+
+#[derive(Resource)]
+pub struct GameRng {
+	seed: u64,
+	pub gameplay: StdRng,
+	pub visual: StdRng,
+}
+
+impl GameRng {
+	pub fn new(seed: u64) -> Self {
+		Self {
+			seed,
+			gameplay: stream(seed, 0, GAMEPLAY_STREAM),
+			visual: stream(seed, 0, VISUAL_STREAM),
+		}
+	}
+
+	pub fn seed(&self) -> u64 { self.seed }
+
+	// same seed and stage, same snacks - whatever happened on the stages before.
+	pub fn start_stage(&mut self, campaign_index: usize) {
+		self.gameplay = stream(self.seed, campaign_index as u64, GAMEPLAY_STREAM);
+		self.visual = stream(self.seed, campaign_index as u64, VISUAL_STREAM);
+	}
+}
+
+// spread seed, stage and stream apart so neighbouring seeds don't share streams.
+fn stream(seed: u64, stage: u64, stream: u64) -> StdRng {
+	let key = seed
+		^ stage.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+		^ stream.wrapping_add(1).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+	StdRng::seed_from_u64(key)
+}
+
+// accepts `--seed 42` and `--seed=42`.
+fn seed_from_args(args: impl Iterator<Item = String>) -> Option<u64> {
+	let mut args = args.skip(1);
+	while let Some(arg) = args.next() {
+		let value = if arg == "--seed" {
+			args.next()
+		} else if let Some(value) = arg.strip_prefix("--seed=") {
+			Some(value.to_string())
+		} else { continue; };
+
+		match value.as_deref().map(str::parse::<u64>) {
+			Some(Ok(seed)) => return Some(seed),
+			_ => println!("rng: --seed needs a whole number, picking one at random"),
+		}
+	}
+	None
+}
+
+fn show_seed_on_death(
+	mut gamestate_events: EventReader<GameStateEvent>,
+	mut ui_writer: EventWriter<UIEvent>,
+	game_rng: Res<GameRng>,
+) {
+	for e in gamestate_events.read() {
+		let GameStateData::Death = e.data else { continue; };
+		ui_writer.write(UIEvent { id: "info", text: format!("seed: {} - run with --seed {} to play it again", game_rng.seed(), game_rng.seed()) });
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn args(args: &[&str]) -> impl Iterator<Item = String> {
+		args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter()
+	}
+
+	#[test]
+	fn seed_is_read_from_the_command_line() {
+		assert_eq!(seed_from_args(args(&["snakes", "--seed", "42"])), Some(42));
+		assert_eq!(seed_from_args(args(&["snakes", "--seed=7"])), Some(7));
+		assert_eq!(seed_from_args(args(&["snakes", "--seed", "many"])), None);
+		assert_eq!(seed_from_args(args(&["snakes"])), None);
+	}
+
+	#[test]
+	fn streams_replay_per_stage_and_stay_apart() {
+		let mut a = GameRng::new(5);
+		let mut b = GameRng::new(5);
+		a.start_stage(3);
+		let _: u32 = a.visual.random(); // visuals don't move gameplay along
+		b.start_stage(3);
+		assert_eq!(a.gameplay.random::<u64>(), b.gameplay.random::<u64>());
+
+		a.start_stage(3);
+		assert_ne!(a.gameplay.random::<u64>(), a.visual.random::<u64>());
+	}
+}
@@ -0,0 +1,115 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+use crate::state::{ GameStateData, GameStateEvent };
+
+// shared assets plugin: one mesh per size and one material per color,
+// shared by every tile, segment and snack that uses them.
+// they're created the first time they're asked for and kept for the whole run,
+// so asset counts stay flat however many times a stage is set.
+
+pub struct SharedAssetsPlugin;
+
+impl Plugin for SharedAssetsPlugin {
+	fn build(&self, app: &mut App) {
+		app.init_resource::<SharedAssetCache>();
+		app.add_systems(Update, print_asset_counts);
+	}
+}
+
+#[derive(Resource, Default)]
+pub struct SharedAssetCache {
+	cuboids: HashMap<[u32; 3], Handle<Mesh>>, // f32 bits of the size
+	meshes: HashMap<&'static str, Handle<Mesh>>,
+	materials: HashMap<[u32; 4], Handle<StandardMaterial>>, // f32 bits of the srgba color
+	custom_materials: HashMap<&'static str, Handle<StandardMaterial>>,
+}
+
+// the cache plus the asset collections it fills, as a single system param.
+#[derive(SystemParam)]
+pub struct SharedAssets<'w> {
+	cache: ResMut<'w, SharedAssetCache>,
+	meshes: ResMut<'w, Assets<Mesh>>,
+	materials: ResMut<'w, Assets<StandardMaterial>>,
+}
+
+impl SharedAssets<'_> {
+	pub fn cuboid(&mut self, size: Vec3) -> Handle<Mesh> {
+		let key = size.to_array().map(f32::to_bits);
+		self.cache.cuboids
+			.entry(key)
+			.or_insert_with(|| self.meshes.add(Cuboid::new(size.x, size.y, size.z)))
+			.clone()
+	}
+
+	// for anything that isn't a cuboid, cached by name.
+	pub fn mesh(&mut self, name: &'static str, mesh: impl FnOnce() -> Mesh) -> Handle<Mesh> {
+		self.cache.meshes
+			.entry(name)
+			.or_insert_with(|| self.meshes.add(mesh()))
+			.clone()
+	}
+
+	pub fn material(&mut self, color: Color) -> Handle<StandardMaterial> {
+		let key = color.to_srgba().to_f32_array().map(f32::to_bits);
+		self.cache.materials
+			.entry(key)
+			.or_insert_with(|| self.materials.add(color))
+			.clone()
+	}
+
+	// for materials that need more than a color, cached by name.
+	pub fn custom_material(&mut self, name: &'static str, material: impl FnOnce() -> StandardMaterial) -> Handle<StandardMaterial> {
+		self.cache.custom_materials
+			.entry(name)
+			.or_insert_with(|| self.materials.add(material()))
+			.clone()
+	}
+}
+
+fn print_asset_counts(
+	mut gamestate_events: EventReader<GameStateEvent>,
+	meshes: Res<Assets<Mesh>>,
+	materials: Res<Assets<StandardMaterial>>,
+	cache: Res<SharedAssetCache>,
+) {
+	for e in gamestate_events.read() {
+		let GameStateData::Reset(_) = e.data else { continue; };
+		println!("assets: {} meshes ({} shared), {} materials ({} shared)",
+			meshes.len(),
+			cache.cuboids.len() + cache.meshes.len(),
+			materials.len(),
+			cache.materials.len() + cache.custom_materials.len(),
+		);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn spawn_a_stage(mut shared_assets: SharedAssets) {
+		for i in 0..100 {
+			let color = if i % 2 == 0 { Color::srgb_u8(120, 120, 120) } else { Color::srgb_u8(60, 60, 60) };
+			shared_assets.cuboid(Vec3::splat(0.94));
+			shared_assets.material(color);
+		}
+	}
+
+	#[test]
+	fn asset_counts_stay_flat_across_stages() {
+		let mut app = App::new();
+		app.add_plugins((MinimalPlugins, AssetPlugin::default()));
+		app.init_asset::<Mesh>();
+		app.init_asset::<StandardMaterial>();
+		app.init_resource::<SharedAssetCache>();
+		app.add_systems(Update, spawn_a_stage);
+
+		for _ in 0..3 {
+			app.update();
+			assert_eq!(app.world().resource::<Assets<Mesh>>().len(), 1);
+			assert_eq!(app.world().resource::<Assets<StandardMaterial>>().len(), 2);
+		}
+	}
+}
@@ -1,53 +1,139 @@
 use bevy::prelude::*;
-use crate::stage::{ StageEvent, StageEventData };
+use rand::Rng;
+use crate::anim::ShrinkAnim;
+use crate::shared_assets::SharedAssets;
+use crate::stage::{ StageCoordinate, StageEvent, StageEventData };
 
 const SNACK_Y: f32 = 1.4;
+const SNACK_COLOR: Color = Color::srgb_u8(220, 220, 100);
+const GOLDEN_COLOR: Color = Color::srgb_u8(255, 190, 40);
+const SLOW_DOWN_COLOR: Color = Color::srgb_u8(90, 140, 240);
+const SHRINK_COLOR: Color = Color::srgb_u8(230, 80, 80);
+const BONUS_TIME_COLOR: Color = Color::srgb_u8(110, 230, 140);
+const GOLDEN_SCORE: u32 = 3;
+const EXPIRE_SHRINK_SPEED: f32 = 2.0;
+const COUNTDOWN_OFFSET: Vec3 = Vec3::new(0.0, 0.8, -0.6); // from the snack, towards the top of the screen
+const COUNTDOWN_FONT_SIZE: f32 = 16.0;
 
-// snacks plugin: passive plugin - snacks spawn, rotate, despawn.
+// snacks plugin: passive plugin - snacks spawn, rotate, despawn, and count down if told to.
+// what a snack does when eaten is up to the stage (score) and the snakes (everything else).
 
 pub struct SnacksPlugin;
 
 impl Plugin for SnacksPlugin {
 	fn build(&self, app: &mut App) {
-		app.add_systems(Update, (read_stage_events, update_snacks).chain());
+		app.add_systems(Update, (read_stage_events, update_snacks, update_countdowns).chain());
 	}
 }
 
 #[derive(Component)]
 pub struct Snack {
 	rotate_speed: f32,
+	coordinate: StageCoordinate,
+}
+
+// seconds left on a snack, as ui text that follows it around the screen.
+#[derive(Component)]
+struct SnackCountdown {
+	coordinate: StageCoordinate,
+	expires_at: f32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SnackKind {
+	#[default]
+	Normal,
+	Golden, // worth extra score
+	SlowDown, // lowers move speed instead of raising it
+	Shrink, // drops the last segment instead of growing one
+	BonusTime, // adds time on stages with a time limit
+}
+
+impl SnackKind {
+	pub const ALL: [SnackKind; 5] = [SnackKind::Normal, SnackKind::Golden, SnackKind::SlowDown, SnackKind::Shrink, SnackKind::BonusTime];
+
+	pub fn score(&self) -> u32 {
+		match self {
+			SnackKind::Golden => GOLDEN_SCORE,
+			_ => 1,
+		}
+	}
+
+	// weights line up with ALL. all zero falls back to a normal snack.
+	pub fn pick(weights: &[u32; 5], rng: &mut impl Rng) -> Self {
+		let total: u32 = weights.iter().sum();
+		if total == 0 { return SnackKind::Normal; }
+
+		let mut roll = rng.random_range(0..total);
+		for (kind, weight) in SnackKind::ALL.iter().zip(weights) {
+			if roll < *weight { return *kind; }
+			roll -= weight;
+		}
+		SnackKind::Normal
+	}
 }
 
 fn read_stage_events(
 	mut stage_events: EventReader<StageEvent>,
 	mut commands: Commands,
-	mut meshes: ResMut<Assets<Mesh>>,
-	mut materials: ResMut<Assets<StandardMaterial>>,
-	query: Query<Entity, With<Snack>>
+	mut shared_assets: SharedAssets,
+	query: Query<(Entity, &Snack)>,
+	countdowns: Query<(Entity, &SnackCountdown)>,
 ) {
 	for e in stage_events.read() {
 		match e.data {
-			StageEventData::SpawnSnack(spawn_point) => {
-				for entity in query {
-					commands.entity(entity).despawn();
-				}
+			StageEventData::SpawnSnack(spawn_point, kind) => {
 				// println!("...spawning next snack!");
+				let (mesh, color) = match kind {
+					SnackKind::Normal => (shared_assets.mesh("snack", || Tetrahedron::default().into()), SNACK_COLOR),
+					SnackKind::Golden => (shared_assets.mesh("snack_golden", || Sphere::new(0.6).mesh().ico(1).unwrap()), GOLDEN_COLOR),
+					SnackKind::SlowDown => (shared_assets.mesh("snack_slow_down", || Cylinder::new(0.5, 0.4).into()), SLOW_DOWN_COLOR),
+					SnackKind::Shrink => (shared_assets.mesh("snack_shrink", || Cuboid::new(0.7, 0.7, 0.7).into()), SHRINK_COLOR),
+					SnackKind::BonusTime => (shared_assets.mesh("snack_bonus_time", || Torus::new(0.25, 0.55).into()), BONUS_TIME_COLOR),
+				};
 				commands.spawn((
-					Mesh3d(meshes.add(Tetrahedron::default())),
-					MeshMaterial3d(materials.add(Color::srgb_u8(220, 220, 100))),
+					Mesh3d(mesh),
+					MeshMaterial3d(shared_assets.material(color)),
 					Transform {
 						translation: Vec3 { x: spawn_point.x as f32, y: SNACK_Y, z: spawn_point.y as f32 },
 						rotation: Quat::IDENTITY,
 						scale: Vec3::new(0.6, 0.6, 0.6),
 					},
-					Snack {rotate_speed: 1.0},
+					Snack {rotate_speed: 1.0, coordinate: spawn_point},
+				));
+			}
+			StageEventData::RemoveSnack(coordinate) => {
+				for (entity, snack) in query {
+					if snack.coordinate.equals(&coordinate) {
+						commands.entity(entity).despawn();
+					}
+				}
+				despawn_countdowns_at(Some(coordinate), &countdowns, &mut commands);
+			}
+			StageEventData::ExpireSnack(coordinate) => {
+				// no longer a snack, just something shrinking away.
+				for (entity, snack) in query {
+					if snack.coordinate.equals(&coordinate) {
+						commands.entity(entity).remove::<Snack>().insert(ShrinkAnim::new(EXPIRE_SHRINK_SPEED));
+					}
+				}
+				despawn_countdowns_at(Some(coordinate), &countdowns, &mut commands);
+			}
+			StageEventData::SnackCountdown(coordinate, expires_at) => {
+				commands.spawn((
+					SnackCountdown { coordinate, expires_at },
+					Text::new(""),
+					TextFont { font_size: COUNTDOWN_FONT_SIZE, ..default() },
+					Node { position_type: PositionType::Absolute, ..default() },
+					Visibility::Hidden,
 				));
 			}
-			StageEventData::ClearSnack => {
+			StageEventData::ClearSnacks => {
 				// println!("... clear snack");
-				for entity in query {
+				for (entity, _snack) in query {
 					commands.entity(entity).despawn();
 				}
+				despawn_countdowns_at(None, &countdowns, &mut commands);
 			}
 			_ => {}
 		}
@@ -64,3 +150,60 @@ fn update_snacks(
 		transform.rotate_z((time.delta_secs() / 3.) * snack.rotate_speed );
 	}
 }
+
+// None for all of them.
+fn despawn_countdowns_at(
+	coordinate: Option<StageCoordinate>,
+	countdowns: &Query<(Entity, &SnackCountdown)>,
+	commands: &mut Commands,
+) {
+	for (entity, countdown) in countdowns {
+		if coordinate.is_none_or(|coordinate| countdown.coordinate.equals(&coordinate)) {
+			commands.entity(entity).despawn();
+		}
+	}
+}
+
+fn update_countdowns(
+	time: Res<Time>,
+	cameras: Query<(&Camera, &GlobalTransform)>,
+	query: Query<(&SnackCountdown, &mut Text, &mut Node, &mut Visibility)>,
+) {
+	let Ok((camera, camera_transform)) = cameras.single() else { return; };
+
+	for (countdown, mut text, mut node, mut visibility) in query {
+		let seconds_left = countdown.expires_at - time.elapsed_secs();
+		let snack_translation = Vec3::new(countdown.coordinate.x as f32, SNACK_Y, countdown.coordinate.y as f32);
+		let Ok(position) = camera.world_to_viewport(camera_transform, snack_translation + COUNTDOWN_OFFSET) else {
+			*visibility = Visibility::Hidden;
+			continue;
+		};
+		if seconds_left <= 0.0 {
+			*visibility = Visibility::Hidden;
+			continue;
+		}
+
+		*visibility = Visibility::Inherited;
+		text.0 = format!("{}", seconds_left.ceil() as u32);
+		node.left = Val::Px(position.x);
+		node.top = Val::Px(position.y);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+
+	#[test]
+	fn snack_kinds_follow_their_weights() {
+		let mut rng = StdRng::seed_from_u64(1);
+
+		for _ in 0..100 {
+			assert_eq!(SnackKind::pick(&[0, 0, 0, 0, 0], &mut rng), SnackKind::Normal);
+			assert_eq!(SnackKind::pick(&[0, 0, 0, 5, 0], &mut rng), SnackKind::Shrink);
+			assert_ne!(SnackKind::pick(&[3, 1, 0, 1, 0], &mut rng), SnackKind::SlowDown);
+		}
+	}
+}
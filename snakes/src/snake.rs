@@ -1,22 +1,38 @@
-use bevy::{input::keyboard::KeyboardInput, prelude::*};
+use bevy::prelude::*;
 use bevy::time::common_conditions::on_timer;
 
-use crate::state::{ GameState, GameStateData, GameStateEvent, SnakePlayData };
-use crate::stage::{ StageCoordinate, StageEvent, StageEventData };
+use crate::state::{ GameState, GameStateData, GameStateEvent };
+use crate::stage::{ StageBehaviourMask, StageCoordinate, StageEvent, StageEventData, TileBehaviour };
 use crate::anim::{ TumbleAnim };
+use crate::actions::{ Action, ActionEvent, Controls };
+use crate::players::PlayersConfig;
+use crate::rng::GameRng;
+use crate::shared_assets::SharedAssets;
+use crate::snacks::SnackKind;
 
+use std::collections::VecDeque;
 use std::time::Duration;
 
-// snake plugin: snakes input and movement for up to 3 players
+// snake plugin: snakes input and movement, one snake per player in the players config.
 
 const SNAKE_HEAD_SIZE: Vec3 = Vec3::new(1.0, 0.8, 1.0);
 const SNAKE_SEGMENT_SIZE: Vec3 = Vec3::new(0.68, 0.6, 0.68);
 const SNAKE_Y: f32 = 1.4;
 const HIDDEN_COORDINATE: StageCoordinate = StageCoordinate::new(1000, 1000);
 
-const SNAKE_COLOR_1: Color = Color::srgb_u8(220, 100, 220);
-const SNAKE_COLOR_2: Color = Color::srgb_u8(80, 220, 220);
-const SNAKE_COLOR_3: Color = Color::srgb_u8(120, 220, 120);
+// by snake id, from 1.
+const SNAKE_COLORS: [Color; 8] = [
+	Color::srgb_u8(220, 100, 220),
+	Color::srgb_u8(80, 220, 220),
+	Color::srgb_u8(120, 220, 120),
+	Color::srgb_u8(240, 170, 60),
+	Color::srgb_u8(240, 240, 240),
+	Color::srgb_u8(90, 110, 240),
+	Color::srgb_u8(240, 90, 90),
+	Color::srgb_u8(170, 120, 80),
+];
+const FALLBACK_SNAKE_COLOR: Color = Color::srgb(0.4, 0.4, 0.4);
+const MAX_QUEUED_TURNS: usize = 3;
 
 const DEBUG_SNAKES_WALKABLE_MASK: bool = false;
 
@@ -24,6 +40,7 @@ pub struct SnakePlugin;
 
 impl Plugin for SnakePlugin {
 	fn build(&self, app: &mut App) {
+		app.insert_resource(PlayersConfig::load());
 		app.add_systems(Startup, init_snakes);
 		app.add_systems(Update,
 			(
@@ -32,6 +49,7 @@ impl Plugin for SnakePlugin {
 				read_input,
 				move_snakes,
 				spawn_segments,
+				drop_segments,
 				move_segments,
 				update_segments,
 				evaluate_all_falling.run_if(on_timer(Duration::from_secs(2))),
@@ -45,6 +63,7 @@ pub struct Snake {
 	pub id: u32,
 	pub direction: Direction,
 	pub last_direction_moved: Direction,
+	turns: VecDeque<Direction>, // one is taken per move tick
 	pub falling: bool,
 	pub fall_duration: u32,
 	pub segments: u32,
@@ -52,6 +71,7 @@ pub struct Snake {
 	pub active: bool,
 	pub input_received: bool,
 	pub had_a_snack: bool,
+	pub drop_segment: bool, // had a shrink snack
 }
 
 impl Snake {
@@ -60,22 +80,35 @@ impl Snake {
 			id,
 			direction: Direction::Up,
 			last_direction_moved: Direction::None,
+			turns: VecDeque::new(),
 			falling: false,
 			fall_duration: 0,
 			segments: 0,
 			stage_coordinate: HIDDEN_COORDINATE,
 			active,
 			input_received: false,
-			had_a_snack: false
+			had_a_snack: false,
+			drop_segment: false,
 		}
 	}
 
-	fn set_direction(&mut self, direction: Direction) {
-		if is_opposite_direction(&self.last_direction_moved, &direction) {
+	// quick presses between two moves all count, e.g. up then left for a u-turn.
+	// each turn is checked against the one queued before it, or the last move if there's none.
+	fn queue_turn(&mut self, direction: Direction) {
+		let previous = self.turns.back().copied().unwrap_or(self.last_direction_moved);
+		if direction == previous { return; }
+		if is_opposite_direction(&previous, &direction) {
 			println!("-- snake can't turn around on itself!");
 			return;
 		}
-		self.direction = direction;
+		if self.turns.len() >= MAX_QUEUED_TURNS { return; }
+		self.turns.push_back(direction);
+	}
+
+	fn next_turn(&mut self) {
+		if let Some(direction) = self.turns.pop_front() {
+			self.direction = direction;
+		}
 	}
 }
 
@@ -98,15 +131,7 @@ impl Segment {
 	}
 }
 
-#[derive(Component)]
-pub struct InputMapping {
-	pub up: KeyCode,
-	pub down: KeyCode,
-	pub left: KeyCode,
-	pub right: KeyCode,
-}
-
-#[derive(Component, Copy, Clone)]
+#[derive(Component, Copy, Clone, Debug, PartialEq)]
 pub enum Direction {
 	None,
 	Up,
@@ -115,64 +140,33 @@ pub enum Direction {
 	Right,
 }
 
-impl InputMapping {
-	fn new(
-		up: KeyCode, 
-		down: KeyCode, 
-		left: KeyCode, 
-		right: KeyCode
-	) -> Self {
-		Self {
-			up, down, left, right
-		}
-	}
+pub fn snake_color(snake_id: u32) -> Color {
+	snake_id.checked_sub(1)
+		.and_then(|i| SNAKE_COLORS.get(i as usize))
+		.copied()
+		.unwrap_or(FALLBACK_SNAKE_COLOR)
 }
 
 fn init_snakes(
 	mut commands: Commands,
-	mut meshes: ResMut<Assets<Mesh>>,
-	mut materials: ResMut<Assets<StandardMaterial>>,
+	mut shared_assets: SharedAssets,
+	players: Res<PlayersConfig>,
+	controls: Res<Controls>,
 ) {
-	println!("init snakes");
-
-	commands.spawn((
-		Snake::new(1, true),
-		Direction::Up,
-		InputMapping::new(
-			KeyCode::ArrowUp,
-			KeyCode::ArrowDown,
-			KeyCode::ArrowLeft,
-			KeyCode::ArrowRight),
-		Transform::from_xyz(HIDDEN_COORDINATE.x as f32, 0.0, HIDDEN_COORDINATE.y as f32),
-		Mesh3d(meshes.add(Cuboid::new(SNAKE_HEAD_SIZE.x, SNAKE_HEAD_SIZE.y, SNAKE_HEAD_SIZE.z))),
-		MeshMaterial3d(materials.add(SNAKE_COLOR_1)),
-	));
-
-	commands.spawn((
-		Snake::new(2, false),
-		Direction::Up,
-		InputMapping::new(
-			KeyCode::KeyW,
-			KeyCode::KeyS,
-			KeyCode::KeyA,
-			KeyCode::KeyD),
-		Transform::from_xyz(HIDDEN_COORDINATE.x as f32, 0.0, HIDDEN_COORDINATE.y as f32),
-		Mesh3d(meshes.add(Cuboid::new(SNAKE_HEAD_SIZE.x, SNAKE_HEAD_SIZE.y, SNAKE_HEAD_SIZE.z))),
-		MeshMaterial3d(materials.add(SNAKE_COLOR_2)),
-	));
-
-	commands.spawn((
-		Snake::new(3, false),
-		Direction::Up,
-		InputMapping::new(
-			KeyCode::KeyI,
-			KeyCode::KeyK,
-			KeyCode::KeyJ,
-			KeyCode::KeyL),
-		Transform::from_xyz(HIDDEN_COORDINATE.x as f32, 0.0, HIDDEN_COORDINATE.y as f32),
-		Mesh3d(meshes.add(Cuboid::new(SNAKE_HEAD_SIZE.x, SNAKE_HEAD_SIZE.y, SNAKE_HEAD_SIZE.z))),
-		MeshMaterial3d(materials.add(SNAKE_COLOR_3)),
-	));
+	println!("init snakes: {} players", players.count);
+
+	for snake_id in players.ids() {
+		if !controls.has_keys(snake_id) {
+			println!("init snakes: snake {} has no keys, it can only join with a gamepad", snake_id);
+		}
+		commands.spawn((
+			Snake::new(snake_id, snake_id == 1),
+			Direction::Up,
+			Transform::from_xyz(HIDDEN_COORDINATE.x as f32, 0.0, HIDDEN_COORDINATE.y as f32),
+			Mesh3d(shared_assets.cuboid(SNAKE_HEAD_SIZE)),
+			MeshMaterial3d(shared_assets.material(snake_color(snake_id))),
+		));
+	}
 }
 
 fn read_gamestate_events(
@@ -188,15 +182,16 @@ fn read_gamestate_events(
 					snake.segments = 0;
 					snake.last_direction_moved = Direction::None;
 					snake.direction = Direction::Up;
+					snake.turns.clear();
 					snake.input_received = false;
+					snake.had_a_snack = false;
+					snake.drop_segment = false;
 					snake.stage_coordinate = HIDDEN_COORDINATE;
 				}
-				GameStateData::Start => {
-					if snake.active {
-						transform.translation = Vec3::new(snake.stage_coordinate.x as f32, SNAKE_Y, snake.stage_coordinate.y as f32);
-					}
+				GameStateData::Start if snake.active => {
+					transform.translation = Vec3::new(snake.stage_coordinate.x as f32, SNAKE_Y, snake.stage_coordinate.y as f32);
 				}
-				GameStateData::Reset(_counter) => {
+				GameStateData::Edit(_) | GameStateData::Reset(_) => {
 					transform.translation = Vec3::new(HIDDEN_COORDINATE.x as f32, SNAKE_Y, HIDDEN_COORDINATE.y as f32);
 				}
 				_ => {}
@@ -218,21 +213,26 @@ fn read_stage_events (
 					if spawn_point_data.snake_id != snake.id { continue; }
 					snake.stage_coordinate = spawn_point_data.spawn_point;
 				}
-				StageEventData::SnackEaten(snake_id) => {
-					if let GameStateData::Play(play_data) = &mut game_state.data {
-						if snake_id == snake.id {
-							println!("snake {} had a lil snack!", snake_id);
-							snake.had_a_snack = true;
-							play_data.increment_speed();
+				StageEventData::SnackEaten(snake_id, kind) => {
+					if let GameStateData::Play(play_data) = &mut game_state.data
+					&& snake_id == snake.id {
+						println!("snake {} had a lil {:?} snack!", snake_id, kind);
+						match kind {
+							SnackKind::Shrink => snake.drop_segment = true,
+							_ => snake.had_a_snack = true,
 						}
+						match kind {
+							SnackKind::SlowDown => play_data.slow_down(),
+							_ => play_data.increment_speed(),
+						}
+						if kind == SnackKind::BonusTime { play_data.add_bonus_time(); }
 					}
 				}
 				StageEventData::SnakeFalling(snake_id) => {
-					if let GameStateData::Play(_play_data) = &mut game_state.data {
-						if snake_id == snake.id {
-							snake.falling = true;
-							println!("snake {} is falling!", &snake_id);
-						}
+					if let GameStateData::Play(_play_data) = &mut game_state.data
+					&& snake_id == snake.id {
+						snake.falling = true;
+						println!("snake {} is falling!", &snake_id);
 					}
 				}
 				_ => {}
@@ -242,15 +242,21 @@ fn read_stage_events (
 }
 
 fn read_input(
-	mut key_events: EventReader<KeyboardInput>,
-	mut query: Query<(&mut Snake, &mut InputMapping)>,
+	mut action_events: EventReader<ActionEvent>,
+	mut query: Query<&mut Snake>,
+	game_state: Res<GameState>,
 ) {	
-	for e in key_events.read() {
-		for (mut snake, input_mapping) in &mut query {
-			if e.key_code == input_mapping.up { snake.set_direction(Direction::Up); snake.input_received = true; }
-			else if e.key_code == input_mapping.down { snake.set_direction(Direction::Down); snake.input_received = true; }
-			else if e.key_code == input_mapping.left { snake.set_direction(Direction::Left); snake.input_received = true; }
-			else if e.key_code == input_mapping.right { snake.set_direction(Direction::Right); snake.input_received = true; }
+	// the editor and the controls screen use the same keys for themselves.
+	if let GameStateData::Edit(_) | GameStateData::Rebind(_) = game_state.data {
+		action_events.clear();
+		return;
+	}
+	for e in action_events.read() {
+		let (Some(snake_id), Action::Turn(direction)) = (e.snake_id, e.action) else { continue; };
+		for mut snake in &mut query {
+			if snake.id != snake_id { continue; }
+			snake.queue_turn(direction);
+			snake.input_received = true;
 		}
 	}
 } 
@@ -263,7 +269,8 @@ fn move_snakes(
 	match &mut game_state.data {
 		GameStateData::Start => {
 			for(mut snake, mut transform) in query {
-				if !snake.active && snake.input_received { 
+				// snakes without a spawn point on this stage sit it out.
+				if !snake.active && snake.input_received && snake.stage_coordinate != HIDDEN_COORDINATE { 
 					snake.active = true;
 					transform.translation = Vec3::new(snake.stage_coordinate.x as f32, SNAKE_Y, snake.stage_coordinate.y as f32);
 				}
@@ -275,12 +282,7 @@ fn move_snakes(
 			let mut snakes_moved = false;
 
 			for(mut snake, mut transform) in query {
-				let snake_data: &mut SnakePlayData = match snake.id {
-					1 => { &mut play_data.snake1_data }
-					2 => { &mut play_data.snake2_data }
-					3 => { &mut play_data.snake3_data }
-					_=> { return; }
-				};
+				let Some(snake_data) = play_data.snakes.get_mut(&snake.id) else { continue; };
 
 				snake_data.active = snake.active;
 				snake_data.falling = snake.falling;
@@ -290,25 +292,34 @@ fn move_snakes(
 
 				if !snake.active { continue; }
 				let next_translation: Vec3;
+				// the tile a conveyor or portal moved the head on from, it has to be free as well.
+				let mut passed: Option<StageCoordinate> = None;
 
 				if snake.falling {
 					snake.fall_duration += 1;
 					next_translation = Vec3::new(snake.stage_coordinate.x as f32, SNAKE_Y - snake.fall_duration as f32, snake.stage_coordinate.y as f32);
 				}	
 				else {
-					match snake.direction {
-						Direction::Up => { snake.stage_coordinate.y -= 1; }
-						Direction::Down => { snake.stage_coordinate.y += 1; }
-						Direction::Left => { snake.stage_coordinate.x -= 1; }
-						Direction::Right => { snake.stage_coordinate.x += 1; }
-						Direction::None => {}
-					}
+					snake.next_turn();
+					snake.stage_coordinate = step(&snake.stage_coordinate, &snake.direction, &play_data.stage_behaviour, play_data.wrap_edges);
 					snake.last_direction_moved = snake.direction;
+					match apply_tile_behaviour(snake.stage_coordinate, snake.direction, &play_data.stage_behaviour, play_data.wrap_edges) {
+						Some(coordinate) => {
+							if coordinate != snake.stage_coordinate { passed = Some(snake.stage_coordinate); }
+							snake.stage_coordinate = coordinate;
+						}
+						None => {
+							println!("woops snake {} ran into a one-way tile!", snake.id);
+							play_data.crash = true;
+						}
+					}
 					next_translation = Vec3::new(snake.stage_coordinate.x as f32, SNAKE_Y, snake.stage_coordinate.y as f32);
 				}
 
-				if play_data.snakes_walkable_mask.contains(&snake.stage_coordinate) 
-				&& !play_data.snakes_walkable_mask.get(&snake.stage_coordinate) 
+				// conveyor and portal tiles never close or collapse, so only snakes can be in the way there.
+				let occupied = |coordinate: &StageCoordinate| play_data.snakes_walkable_mask.contains(coordinate)
+					&& !play_data.snakes_walkable_mask.get(coordinate);
+				if (occupied(&snake.stage_coordinate) || passed.as_ref().is_some_and(occupied))
 				&& !snake.falling {
 					// crash!
 					println!("woops snake {} crashed!", snake.id);
@@ -341,51 +352,61 @@ fn spawn_segments(
 	mut game_state: ResMut<GameState>,
 	query: Query<&mut Snake>,
 	mut commands: Commands,
-	mut meshes: ResMut<Assets<Mesh>>,
-	mut materials: ResMut<Assets<StandardMaterial>>,
+	mut shared_assets: SharedAssets,
 ) {
 	if let GameStateData::Play(play_data) = &mut game_state.data {
 		for mut snake in query {
 			if !snake.had_a_snack { continue; }
-			let color: Color = match snake.id {
-				1 => { SNAKE_COLOR_1 }
-				2 => { SNAKE_COLOR_2 }
-				3 => { SNAKE_COLOR_3 }
-				_ => { Color::srgb(0.4, 0.4, 0.4) }
-			};
 			commands.spawn((
 				Segment::new(snake.id, snake.stage_coordinate),
 				Transform::from_xyz(snake.stage_coordinate.x as f32, SNAKE_Y, snake.stage_coordinate.y as f32),
-				Mesh3d(meshes.add(Cuboid::new(SNAKE_SEGMENT_SIZE.x, SNAKE_SEGMENT_SIZE.y, SNAKE_SEGMENT_SIZE.z))),
-				MeshMaterial3d(materials.add(color)),
+				Mesh3d(shared_assets.cuboid(SNAKE_SEGMENT_SIZE)),
+				MeshMaterial3d(shared_assets.material(snake_color(snake.id))),
 			));
 			snake.segments += 1;
 			snake.had_a_snack = false;
 			// this data lags behind until next time segments are moved
-			match snake.id {
-				1 => { play_data.snake1_data.had_a_snack = true; }
-				2 => { play_data.snake2_data.had_a_snack = true; }
-				3 => { play_data.snake3_data.had_a_snack = true; }
-				_ => {}
+			if let Some(snake_data) = play_data.snakes.get_mut(&snake.id) {
+				snake_data.had_a_snack = true;
 			}
 		}
 	}
 }
 
+// the tail is the segment that's been waiting longest to move up behind the head.
+// dropping it keeps the other move counters lined up with one segment less.
+fn drop_segments(
+	mut game_state: ResMut<GameState>,
+	snake_query: Query<&mut Snake>,
+	segment_query: Query<(Entity, &Segment)>,
+	mut commands: Commands,
+) {
+	let GameStateData::Play(play_data) = &mut game_state.data else { return; };
+
+	for mut snake in snake_query {
+		if !snake.drop_segment { continue; }
+		snake.drop_segment = false;
+
+		let tail = segment_query.iter()
+			.filter(|(_entity, segment)| segment.snake_id == snake.id)
+			.max_by_key(|(_entity, segment)| segment.move_counter);
+		let Some((entity, segment)) = tail else { continue; };
+
+		commands.entity(entity).despawn();
+		play_data.snakes_walkable_mask.set(&segment.coordinate, true);
+		snake.segments -= 1;
+	}
+}
+
 fn move_segments(
 	mut game_state: ResMut<GameState>,
 	mut query: Query<(&mut Segment, &mut Transform)>,
 ) {	
 	if let GameStateData::Play(play_data) = &mut game_state.data {
 		for (mut segment, mut transform) in &mut query {
-			let snake_data = match segment.snake_id {
-				1 => { &play_data.snake1_data }
-				2 => { &play_data.snake2_data }
-				3 => { &play_data.snake3_data }
-				_ => { return; }
-			};
+			let Some(snake_data) = play_data.snakes.get(&segment.snake_id) else { continue; };
 
-			if !snake_data.refresh_segments { return; }
+			if !snake_data.refresh_segments { continue; }
 			
 			segment.move_counter += 1;
 
@@ -423,12 +444,10 @@ fn move_segments(
 		}
 
 		// data reset when done.
-		play_data.snake1_data.refresh_segments = false;
-		play_data.snake1_data.had_a_snack = false;
-		play_data.snake2_data.refresh_segments = false;
-		play_data.snake2_data.had_a_snack = false;
-		play_data.snake3_data.refresh_segments = false;
-		play_data.snake3_data.had_a_snack = false;
+		for snake_data in play_data.snakes.values_mut() {
+			snake_data.refresh_segments = false;
+			snake_data.had_a_snack = false;
+		}
 	}
 }
 
@@ -436,6 +455,7 @@ fn update_segments(
 	game_state: ResMut<GameState>,
 	query: Query<(Entity, &mut Segment)>,
 	mut commands: Commands,
+	mut game_rng: ResMut<GameRng>,
 ) {
 	match game_state.data {
 		GameStateData::Reset(_counter) => {
@@ -446,7 +466,7 @@ fn update_segments(
 		GameStateData::Death => {
 			for (entity, mut segment) in query {
 				if !segment.animating {
-					commands.entity(entity).insert(TumbleAnim::new(1.0, true));
+					commands.entity(entity).insert(TumbleAnim::new(1.0, true, &mut game_rng.visual));
 					segment.animating = true;
 				}
 			}
@@ -455,6 +475,52 @@ fn update_segments(
 	}
 }
 
+fn next_coordinate(coordinate: &StageCoordinate, direction: &Direction) -> StageCoordinate {
+	match direction {
+		Direction::Up => StageCoordinate::new(coordinate.x, coordinate.y - 1),
+		Direction::Down => StageCoordinate::new(coordinate.x, coordinate.y + 1),
+		Direction::Left => StageCoordinate::new(coordinate.x - 1, coordinate.y),
+		Direction::Right => StageCoordinate::new(coordinate.x + 1, coordinate.y),
+		Direction::None => *coordinate,
+	}
+}
+
+// one tile in a direction. with wrap_edges, leaving the grid comes back in on the
+// opposite side - the behaviour mask is always the size of the stage.
+pub fn step(coordinate: &StageCoordinate, direction: &Direction, stage_behaviour: &StageBehaviourMask, wrap_edges: bool) -> StageCoordinate {
+	let next = next_coordinate(coordinate, direction);
+	if wrap_edges { next.wrapped(stage_behaviour.width, stage_behaviour.height) } else { next }
+}
+
+// where a head that just stepped onto a tile ends up, or None if it was blocked.
+// a conveyor pushes once per tick, and a portal at the end of the push still teleports.
+// segments follow the head's previous coordinates, so they go wherever the head went.
+pub fn apply_tile_behaviour(
+	coordinate: StageCoordinate,
+	direction: Direction,
+	stage_behaviour: &StageBehaviourMask,
+	wrap_edges: bool,
+) -> Option<StageCoordinate> {
+	let mut coordinate = coordinate;
+	let mut direction = direction;
+
+	if let TileBehaviour::OneWay(allowed) = stage_behaviour.get(&coordinate) 
+	&& allowed != direction { return None; }
+
+	if let TileBehaviour::Conveyor(push) = stage_behaviour.get(&coordinate) {
+		coordinate = step(&coordinate, &push, stage_behaviour, wrap_edges);
+		direction = push;
+		if let TileBehaviour::OneWay(allowed) = stage_behaviour.get(&coordinate) 
+		&& allowed != direction { return None; }
+	}
+
+	if let TileBehaviour::Portal(exit) = stage_behaviour.get(&coordinate) {
+		coordinate = exit;
+	}
+
+	Some(coordinate)
+}
+
 fn is_opposite_direction(a: &Direction, b: &Direction) -> bool {
 	match (a, b) {
 		(Direction::Up, Direction::Down) => { true }
@@ -481,3 +547,170 @@ fn evaluate_all_falling(
 		play_data.all_falling = all_falling;
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::layout::parse_layout;
+
+	fn behaviour(layout: &str) -> StageBehaviourMask {
+		let layout = parse_layout(layout);
+		StageBehaviourMask::from_layout(&layout, layout[0].len(), layout.len())
+	}
+
+	fn moved_onto(x: i32, y: i32, direction: Direction, layout: &str) -> Option<(i32, i32)> {
+		apply_tile_behaviour(StageCoordinate::new(x, y), direction, &behaviour(layout), false)
+			.map(|coordinate| (coordinate.x, coordinate.y))
+	}
+
+	#[test]
+	fn quick_turns_are_queued_for_the_next_moves() {
+		let mut snake = Snake::new(1, true);
+		snake.last_direction_moved = Direction::Up;
+
+		// a u-turn within one move: up, then left, then down.
+		snake.queue_turn(Direction::Left);
+		snake.queue_turn(Direction::Down);
+		snake.next_turn();
+		assert_eq!(snake.direction, Direction::Left);
+		snake.next_turn();
+		assert_eq!(snake.direction, Direction::Down);
+		// nothing queued, keep going
+		snake.next_turn();
+		assert_eq!(snake.direction, Direction::Down);
+	}
+
+	#[test]
+	fn turns_back_onto_the_previous_turn_are_dropped() {
+		let mut snake = Snake::new(1, true);
+		snake.last_direction_moved = Direction::Up;
+
+		snake.queue_turn(Direction::Down);
+		assert!(snake.turns.is_empty());
+		snake.queue_turn(Direction::Left);
+		snake.queue_turn(Direction::Right);
+		snake.queue_turn(Direction::Left);
+		assert_eq!(snake.turns, [Direction::Left]);
+
+		snake.queue_turn(Direction::Down);
+		snake.queue_turn(Direction::Right);
+		snake.queue_turn(Direction::Up);
+		assert_eq!(snake.turns.len(), MAX_QUEUED_TURNS);
+	}
+
+	#[test]
+	fn conveyors_push_one_extra_tile() {
+		assert_eq!(moved_onto(1, 0, Direction::Right, "A>AA"), Some((2, 0)));
+		// pushed sideways, whatever way the snake was going
+		assert_eq!(moved_onto(1, 1, Direction::Down, "AAA\nAvA\nAAA"), Some((1, 2)));
+		// only one push per tick
+		assert_eq!(moved_onto(1, 0, Direction::Right, "A>>A"), Some((2, 0)));
+	}
+
+	#[test]
+	fn one_way_tiles_block_other_directions() {
+		assert_eq!(moved_onto(1, 0, Direction::Right, "ARA"), Some((1, 0)));
+		assert_eq!(moved_onto(1, 0, Direction::Left, "ARA"), None);
+		// pushed into a one-way tile against its direction
+		assert_eq!(moved_onto(1, 0, Direction::Right, "A>LA"), None);
+	}
+
+	#[test]
+	fn wrap_edges_bring_the_head_back_on_the_other_side() {
+		let stage_behaviour = behaviour("AAA\nAAA");
+		let wrapped = |x: i32, y: i32, direction: Direction| {
+			let coordinate = step(&StageCoordinate::new(x, y), &direction, &stage_behaviour, true);
+			(coordinate.x, coordinate.y)
+		};
+
+		assert_eq!(wrapped(2, 0, Direction::Right), (0, 0));
+		assert_eq!(wrapped(0, 1, Direction::Left), (2, 1));
+		assert_eq!(wrapped(1, 0, Direction::Up), (1, 1));
+		assert_eq!(wrapped(1, 1, Direction::Down), (1, 0));
+		// without wrapping, the head leaves the grid and falls
+		let fallen = step(&StageCoordinate::new(2, 0), &Direction::Right, &stage_behaviour, false);
+		assert_eq!((fallen.x, fallen.y), (3, 0));
+		// conveyors push across the edge too
+		let pushed = apply_tile_behaviour(StageCoordinate::new(2, 0), Direction::Right, &behaviour("AA>"), true).unwrap();
+		assert_eq!((pushed.x, pushed.y), (0, 0));
+	}
+
+	#[test]
+	fn conveyors_do_not_push_a_head_over_a_body() {
+		use bevy::ecs::system::RunSystemOnce;
+		use crate::players::PlayersConfig;
+		use crate::state::PlayData;
+
+		// A > > A: a head moving right onto the first conveyor is pushed onto the second.
+		let run = |body: StageCoordinate| {
+			let mut world = World::new();
+			world.insert_resource(Time::<()>::default());
+			let mut game_state = GameState { stage_width: 4, stage_height: 1, stage_behaviour: behaviour("A>>A"), ..default() };
+			let mut play_data = PlayData::new(&game_state, &PlayersConfig { count: 1 });
+			play_data.last_move_time = -play_data.move_interval - 1.0;
+			play_data.snakes_walkable_mask.set(&body, false);
+			game_state.data = GameStateData::Play(play_data);
+			world.insert_resource(game_state);
+
+			let mut snake = Snake::new(1, true);
+			snake.stage_coordinate = StageCoordinate::new(0, 0);
+			snake.direction = Direction::Right;
+			world.spawn((snake, Transform::default()));
+			world.run_system_once(move_snakes).unwrap();
+
+			let head = world.query::<&Snake>().single(&world).unwrap().stage_coordinate;
+			let GameStateData::Play(play_data) = &world.resource::<GameState>().data else { panic!("not playing"); };
+			(play_data.crash, head)
+		};
+
+		// a body on the conveyor the push starts from
+		assert_eq!(run(StageCoordinate::new(1, 0)), (true, StageCoordinate::new(2, 0)));
+		// and where the push ends
+		assert_eq!(run(StageCoordinate::new(2, 0)), (true, StageCoordinate::new(2, 0)));
+		// nothing in the way
+		assert_eq!(run(StageCoordinate::new(3, 0)), (false, StageCoordinate::new(2, 0)));
+	}
+
+	#[test]
+	fn shrink_snacks_drop_the_tail() {
+		use bevy::ecs::system::RunSystemOnce;
+		use crate::players::PlayersConfig;
+		use crate::state::PlayData;
+
+		let mut world = World::new();
+		let mut game_state = GameState { stage_width: 4, stage_height: 1, ..default() };
+		let mut play_data = PlayData::new(&game_state, &PlayersConfig { count: 1 });
+		for x in 0..3 { play_data.snakes_walkable_mask.set(&StageCoordinate::new(x, 0), false); }
+		game_state.data = GameStateData::Play(play_data);
+		world.insert_resource(game_state);
+
+		let mut snake = Snake::new(1, true);
+		snake.segments = 2;
+		snake.drop_segment = true;
+		world.spawn(snake);
+		let mut spawn_segment = |x: i32, move_counter: u32| {
+			let mut segment = Segment::new(1, StageCoordinate::new(x, 0));
+			segment.move_counter = move_counter;
+			world.spawn(segment).id()
+		};
+		let (neck, tail) = (spawn_segment(1, 1), spawn_segment(2, 2));
+
+		world.run_system_once(drop_segments).unwrap();
+
+		assert!(world.get_entity(neck).is_ok());
+		assert!(world.get_entity(tail).is_err());
+		let snake = world.query::<&Snake>().single(&world).unwrap();
+		assert_eq!((snake.segments, snake.drop_segment), (1, false));
+		let GameStateData::Play(play_data) = &world.resource::<GameState>().data else { panic!("not playing"); };
+		assert!(play_data.snakes_walkable_mask.get(&StageCoordinate::new(2, 0)));
+		assert!(!play_data.snakes_walkable_mask.get(&StageCoordinate::new(1, 0)));
+	}
+
+	#[test]
+	fn portals_move_the_head_to_their_partner() {
+		assert_eq!(moved_onto(1, 0, Direction::Right, "AxAAx"), Some((4, 0)));
+		assert_eq!(moved_onto(4, 0, Direction::Left, "AxAAx"), Some((1, 0)));
+		// a conveyor can push the head into a portal
+		assert_eq!(moved_onto(1, 0, Direction::Right, "A>yAy"), Some((4, 0)));
+	}
+}
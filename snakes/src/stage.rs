@@ -1,14 +1,54 @@
+use bevy::asset::LoadState;
+use bevy::ecs::system::RunSystemOnce;
 use bevy::prelude::*;
-use crate::state::{ GameState, GameStateData, GameStateEvent, PlayData, WinData };
-use crate::anim::{ TumbleAnim, OscillateAnim };
-use std::fs;
+use crate::state::{ GameState, GameStateData, GameStateEvent, PlayData };
+use crate::anim::{ SlideAnim, TumbleAnim };
+use crate::generator::{ self, GeneratorParams };
+use crate::layout::{ 
+	conveyor_direction, door_link, find_portal_pairs, one_way_direction, plate_index, portal_index, spawn_point_id, 
+	validate_layout, LayoutError, StageLayout, StageLayoutLoader, CRUMBLING_TILE, PLATE_TILES,
+};
+use crate::snake::{ apply_tile_behaviour, step, Direction };
+use crate::metadata::{ RevealPattern, SnackPlacement, StageColorsMetadata, StageMetadata };
+use crate::shared_assets::SharedAssets;
+use crate::snacks::SnackKind;
+use crate::rng::GameRng;
+use crate::ui::UIEvent;
 use rand::prelude::*;
+use rand::rngs::StdRng;
+use std::collections::VecDeque;
 
-const LAYOUT_FILEPATH: &str = "./assets/stage_layouts/stage_";
+pub const LAYOUT_FILEPATH: &str = "stage_layouts/stage_";
 const TILE_SIZE: f32 = 0.94;
 const DEFAULT_SPOTLIGHT_INTENSITY: f32 = 7_500_000.0;
 const DEFAULT_STAGE_SETTING_INTERVAL: f32 = 0.3;
 const GLITTER_INTERVAL: f32 = 0.03;
+const PORTAL_COLORS: [Color; 3] = [
+	Color::srgb_u8(150, 90, 230),
+	Color::srgb_u8(230, 130, 60),
+	Color::srgb_u8(60, 170, 230),
+];
+const CONVEYOR_MARKER_COLOR: Color = Color::srgb_u8(230, 230, 230);
+const ONE_WAY_MARKER_COLOR: Color = Color::srgb_u8(230, 80, 80);
+const MARKER_SIZE: Vec3 = Vec3::new(0.24, 0.06, 0.24);
+const CRUMBLING_COLOR: Color = Color::srgb_u8(140, 110, 80);
+const CRUMBLE_TICKS: u32 = 3; // moves between a head leaving a crumbling tile and the tile collapsing
+// by plate index, a plate's doors are a darker shade of it.
+const PLATE_COLORS: [Color; 3] = [
+	Color::srgb_u8(200, 70, 70),
+	Color::srgb_u8(70, 180, 90),
+	Color::srgb_u8(200, 180, 60),
+];
+const DOOR_COLORS: [Color; 3] = [
+	Color::srgb_u8(130, 40, 40),
+	Color::srgb_u8(40, 110, 55),
+	Color::srgb_u8(130, 115, 35),
+];
+const TILE_Y: f32 = 0.5;
+const DOOR_CLOSED_Y: f32 = -0.6; // sunk below the floor, snakes fall in
+const DOOR_SLIDE_SPEED: f32 = 4.0;
+const DROP_HEIGHT: f32 = 8.0; // drop reveal: how far above its place a tile starts
+const DROP_SPEED: f32 = 16.0;
 
 // stage plugin: set stage from textfile data,
 // evaluate snake movements against walkable masks and snack location.
@@ -18,9 +58,12 @@ pub struct StagePlugin;
 impl Plugin for StagePlugin {
 	fn build(&self, app: &mut App) {
 		app.add_event::<StageEvent>();
+		app.init_asset::<StageLayout>();
+		app.init_asset_loader::<StageLayoutLoader>();
 		app.add_systems(Startup, init_stage);
 		app.add_systems(Update, (
 			read_gamestate_events,
+			reload_stage_layout,
 			update_stage,
 			update_tiles,
 			update_spotlight,
@@ -36,10 +79,17 @@ pub struct StageEvent {
 #[derive(Clone)]
 pub enum StageEventData {
 	SetSnakeSpawnPoint(SnakeSpawnPointData),
-	SpawnSnack(StageCoordinate), // coordinate
-	ClearSnack,
-	SnackEaten(u32), // snake id
+	SpawnSnack(StageCoordinate, SnackKind), // adds a snack, any others stay
+	RemoveSnack(StageCoordinate),
+	ExpireSnack(StageCoordinate), // like remove, but it shrinks away
+	SnackCountdown(StageCoordinate, f32), // elapsed seconds the snack there expires at
+	ClearSnacks,
+	SnackEaten(u32, SnackKind), // snake id
 	SnakeFalling(u32), // snake id
+	TileCollapsed(StageCoordinate),
+	DoorOpened(StageCoordinate),
+	DoorClosed(StageCoordinate),
+	TilePainted(StageCoordinate, char), // from the editor
 }
 
 #[derive(Clone, Copy)]
@@ -65,28 +115,79 @@ struct SpotlightBundle {
 #[derive(Component)]
 struct Stage {
 	id: u32,
+	layout_handle: Handle<StageLayout>,
+	layout_pending: bool, // waiting for the asset server to finish loading
+	layout_rejected: bool, // the loaded layout is invalid, waiting for the file to be fixed
 	layout: Vec<String>,
 	width: usize,
 	height: usize,
 	stage_setting_data: StageSettingData,
+	reveal: RevealPattern,
 	camera_translation: Vec3,
 	colors: StageColors,
 	walkable: StageWalkableMask,
-	snack_coordinate: StageCoordinate,
+	behaviour: StageBehaviourMask,
+	crumbling_tiles: Vec<CrumblingTile>,
+	plates: Vec<Plate>,
+	doors: Vec<Door>,
+	last_move_time: f32, // last play_data.last_move_time seen, to count move ticks
+	snacks: Vec<StageSnack>,
+	snack_count: usize, // snacks on the board at once
+	snack_weights: [u32; 5], // lined up with SnackKind::ALL
+	snack_placement: SnackPlacement,
+	snack_lifetime: Option<f32>,
+	snack_countdown: bool,
+	wrap_edges: bool,
 	snack_spawntime: f32,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct StageSnack {
+	coordinate: StageCoordinate,
+	kind: SnackKind,
+	expires_at: Option<f32>, // set once play starts, on stages with a snack lifetime
+}
+
 #[derive(Component)]
 struct Tile {
 	animated: bool,
+	coordinate: StageCoordinate,
 }
 
 impl Tile {
-	fn new() -> Self {
-		Tile { animated: false, }
+	fn new(coordinate: StageCoordinate) -> Self {
+		Tile { animated: false, coordinate }
 	}
 }
 
+#[derive(Debug, Clone)]
+struct CrumblingTile {
+	coordinate: StageCoordinate,
+	stepped_on: bool,
+	ticks_left: u32,
+	collapsed: bool,
+}
+
+impl CrumblingTile {
+	fn new(coordinate: StageCoordinate) -> Self {
+		Self { coordinate, stepped_on: false, ticks_left: CRUMBLE_TICKS, collapsed: false }
+	}
+}
+
+#[derive(Debug, Clone)]
+struct Plate {
+	coordinate: StageCoordinate,
+	index: usize, // links it to the doors with the same plate index
+}
+
+#[derive(Debug, Clone)]
+struct Door {
+	coordinate: StageCoordinate,
+	plate: usize,
+	open_by_default: bool,
+	open: bool,
+}
+
 #[derive(Debug, Copy, Clone)]
 struct StageColors {
 	tiles_a: Color,
@@ -104,14 +205,27 @@ impl StageColors {
 			clear_color: Color::srgb(0.06, 0.06, 0.08),
 		}
 	}
+
+	fn from_metadata(metadata: &StageColorsMetadata) -> Self {
+		let defaults = Self::new();
+		let color = |value: Option<(u8, u8, u8)>, default: Color| {
+			value.map_or(default, |(r, g, b)| Color::srgb_u8(r, g, b))
+		};
+
+		Self {
+			tiles_a: color(metadata.tiles_a, defaults.tiles_a),
+			tiles_b: color(metadata.tiles_b, defaults.tiles_b),
+			tiles_c: color(metadata.tiles_c, defaults.tiles_c),
+			clear_color: color(metadata.clear_color, defaults.clear_color),
+		}
+	}
 }
 
 #[derive(Debug, Clone)]
 struct StageSettingData {
 	interval: f32,
-	current_line: String,
-	x: usize,
-	y: usize,
+	order: Vec<(usize, usize)>, // x, y of every tile, in the order they're placed
+	next: usize, // index into order
 	tile_placed_time: f32,
 	in_progress: bool,
 }
@@ -120,16 +234,75 @@ impl StageSettingData {
 	fn new() -> Self {
 		Self {
 			interval: DEFAULT_STAGE_SETTING_INTERVAL,
-			current_line: String::from("_"),
-			x: 0,
-			y: 0,
+			order: vec![],
+			next: 0,
 			tile_placed_time: 0.0,
 			in_progress: false,
 		}
 	}
 }
 
-#[derive(Copy, Clone, Debug)]
+fn snack_weights(metadata: &StageMetadata) -> [u32; 5] {
+	let weights = &metadata.snack_weights;
+	let mut bonus_time = weights.bonus_time.unwrap_or(0);
+	if bonus_time > 0 && metadata.time_limit.is_none() {
+		println!("stage: bonus time snacks need a time limit, leaving them out");
+		bonus_time = 0;
+	}
+	[
+		weights.normal.unwrap_or(1),
+		weights.golden.unwrap_or(0),
+		weights.slow_down.unwrap_or(0),
+		weights.shrink.unwrap_or(0),
+		bonus_time,
+	]
+}
+
+// heads of the snakes that are still in play, for placing snacks where they can get to.
+fn snake_heads(play_data: &PlayData) -> Vec<StageCoordinate> {
+	play_data.snakes.values()
+		.filter(|data| data.active && !data.falling)
+		.map(|data| data.coordinate)
+		.collect()
+}
+
+// every coordinate of a width * height stage exactly once, in reveal order.
+fn reveal_order(pattern: RevealPattern, width: usize, height: usize, rng: &mut StdRng) -> Vec<(usize, usize)> {
+	let mut order: Vec<(usize, usize)> = (0..height)
+		.flat_map(|y| (0..width).map(move |x| (x, y)))
+		.collect();
+
+	match pattern {
+		RevealPattern::Rows | RevealPattern::Drop => {}
+		RevealPattern::Scatter => order.shuffle(rng),
+		RevealPattern::Diagonal => order.sort_by_key(|&(x, y)| (x + y, x)),
+		RevealPattern::Spiral => {
+			// walk a square spiral out from the center: 1 right, 1 down, 2 left, 2 up, 3 right...
+			// keeping the steps that land on the stage, until every tile has been visited.
+			order.clear();
+			let (mut x, mut y) = ((width / 2) as i32, (height / 2) as i32);
+			let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+			let mut length = 1;
+			let mut turn = 0;
+			order.push((x as usize, y as usize));
+			while order.len() < width * height {
+				let (dx, dy) = directions[turn % 4];
+				for _ in 0..length {
+					x += dx;
+					y += dy;
+					if x >= 0 && y >= 0 && x < width as i32 && y < height as i32 {
+						order.push((x as usize, y as usize));
+					}
+				}
+				turn += 1;
+				if turn % 2 == 0 { length += 1; }
+			}
+		}
+	}
+	order
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct StageCoordinate {
 	pub x: i32,
 	pub y: i32,
@@ -141,6 +314,12 @@ impl StageCoordinate {
 	pub fn equals(&self, other: &StageCoordinate) -> bool {
 		self.x == other.x && self.y == other.y
 	}
+
+	// wrap around to the opposite edge of a width * height grid.
+	pub fn wrapped(&self, width: usize, height: usize) -> Self {
+		if width == 0 || height == 0 { return *self; }
+		Self::new(self.x.rem_euclid(width as i32), self.y.rem_euclid(height as i32))
+	}
 }
 
 #[derive(Debug, Clone)]
@@ -148,20 +327,25 @@ pub struct StageWalkableRow {
 	pub tiles: Vec<bool>,
 }
 
+// always a full width * height rectangle, whatever shape the layout rows had.
 #[derive(Debug, Clone)]
 pub struct StageWalkableMask {
 	pub rows: Vec<StageWalkableRow>,
+	pub width: usize,
+	pub height: usize,
 }
 
 impl StageWalkableMask {
 	pub fn new(width: usize, height: usize) -> Self {		
-		let mut s: StageWalkableMask = StageWalkableMask { rows: vec![] };
+		let mut s: StageWalkableMask = StageWalkableMask { rows: vec![], width: 0, height: 0 };
 		s.init(width, height);
 		s
 	}
 
 	pub fn init(&mut self, width: usize, height: usize) {
 		self.rows.clear();
+		self.width = width;
+		self.height = height;
 		// create a register of walkable true/false data the size of the map layout.
 		for y in 0..height {
 			self.rows.push(StageWalkableRow { tiles:vec![] });
@@ -177,15 +361,14 @@ impl StageWalkableMask {
 		self.rows[coordinate.y as usize].tiles[coordinate.x as usize] = value;
 	}
 
-	pub fn get(&mut self, coordinate: &StageCoordinate) -> bool {
+	pub fn get(&self, coordinate: &StageCoordinate) -> bool {
 		if !self.contains(coordinate) { return false; }
 		self.rows[coordinate.y as usize].tiles[coordinate.x as usize]
 	}
 
-	pub fn contains(&mut self, coordinate: &StageCoordinate) -> bool {
-		if coordinate.y < 0 || coordinate.y >= self.rows.len() as i32 { return false; }
-		if coordinate.x < 0 || coordinate.x >= self.rows[coordinate.y as usize].tiles.len() as i32 { return false; }
-		true
+	pub fn contains(&self, coordinate: &StageCoordinate) -> bool {
+		coordinate.x >= 0 && coordinate.x < self.width as i32
+		&& coordinate.y >= 0 && coordinate.y < self.height as i32
 	}
 
 	pub fn print(&self) {
@@ -200,6 +383,63 @@ impl StageWalkableMask {
 	}
 }
 
+// what a tile does to a snake head that moves onto it, on top of being walkable or not.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TileBehaviour {
+	#[default]
+	None,
+	Portal(StageCoordinate), // the head comes out at the partner portal
+	Conveyor(Direction), // the head is pushed one extra tile
+	OneWay(Direction), // the tile can only be entered moving this way
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StageBehaviourMask {
+	pub rows: Vec<Vec<TileBehaviour>>,
+	pub width: usize,
+	pub height: usize,
+}
+
+impl StageBehaviourMask {
+	pub fn new(width: usize, height: usize) -> Self {
+		Self { rows: vec![vec![TileBehaviour::None; width]; height], width, height }
+	}
+
+	pub fn from_layout(layout: &[String], width: usize, height: usize) -> Self {
+		let mut mask = Self::new(width, height);
+		for (y, line) in layout.iter().enumerate() {
+			for (x, c) in line.chars().enumerate() {
+				let coordinate = StageCoordinate::new(x as i32, y as i32);
+				if let Some(direction) = conveyor_direction(c) {
+					mask.set(&coordinate, TileBehaviour::Conveyor(direction));
+				} else if let Some(direction) = one_way_direction(c) {
+					mask.set(&coordinate, TileBehaviour::OneWay(direction));
+				}
+			}
+		}
+		for (a, b) in find_portal_pairs(layout) {
+			mask.set(&a, TileBehaviour::Portal(b));
+			mask.set(&b, TileBehaviour::Portal(a));
+		}
+		mask
+	}
+
+	pub fn set(&mut self, coordinate: &StageCoordinate, behaviour: TileBehaviour) {
+		if !self.contains(coordinate) { return; }
+		self.rows[coordinate.y as usize][coordinate.x as usize] = behaviour;
+	}
+
+	pub fn get(&self, coordinate: &StageCoordinate) -> TileBehaviour {
+		if !self.contains(coordinate) { return TileBehaviour::None; }
+		self.rows[coordinate.y as usize][coordinate.x as usize]
+	}
+
+	pub fn contains(&self, coordinate: &StageCoordinate) -> bool {
+		coordinate.x >= 0 && coordinate.x < self.width as i32
+		&& coordinate.y >= 0 && coordinate.y < self.height as i32
+	}
+}
+
 #[derive(Component)]
 struct SpotlightData {
 	translation: Vec3,
@@ -231,9 +471,61 @@ fn init_stage(
 	});
 }
 
+// builds a whole stage from a layout in one call and leaves the game at Start, ready for Play -
+// no asset server, no reveal. fast-forward uses it when the layout arrives, tests and tools can too.
+// the world needs the resources and events the stage plugin uses, but not the plugin:
+// without it the stage is spawned bare, without a camera.
+pub fn build_stage_now(world: &mut World, stage_id: u32, layout: Vec<String>) -> Result<(), LayoutError> {
+	if world.query::<&Stage>().iter(world).next().is_none() {
+		world.spawn(Stage::new());
+	}
+	world.run_system_once_with(build_stage, (stage_id, layout))
+		.expect("build_stage_now: the world is missing stage resources or events")
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build_stage(
+	In((stage_id, layout)): In<(u32, Vec<String>)>,
+	mut event_writer: EventWriter<StageEvent>,
+	mut gamestate_writer: EventWriter<GameStateEvent>,
+	mut ui_writer: EventWriter<UIEvent>,
+	mut game_state: ResMut<GameState>,
+	mut game_rng: ResMut<GameRng>,
+	time: Res<Time>,
+	mut commands: Commands,
+	mut shared_assets: SharedAssets,
+	mut stage_query: Query<&mut Stage>,
+	tile_query: Query<Entity, With<Tile>>,
+) -> Result<(), LayoutError> {
+	let game_rng = &mut *game_rng;
+	for mut stage in &mut stage_query {
+		stage.id = stage_id;
+		stage.build_now(layout.clone(), &mut event_writer, &mut commands, &mut shared_assets, &mut game_rng.visual)?;
+		for entity in &tile_query { commands.entity(entity).despawn(); }
+		stage.fill_snacks(&mut event_writer, &mut game_rng.gameplay, time.elapsed_secs());
+
+		game_state.stage = stage_id;
+		game_state.stage_width = stage.width;
+		game_state.stage_height = stage.height;
+		game_state.stage_behaviour = stage.behaviour.clone();
+	}
+	game_state.set_data(GameStateData::Start, &mut gamestate_writer, &mut ui_writer);
+	Ok(())
+}
+
+// a broken layout isn't worth crashing over - say what's wrong and wait for the file to be fixed.
+fn show_layout_error(stage_id: u32, error: &impl std::fmt::Display, ui_writer: &mut EventWriter<UIEvent>) {
+	println!("stage: layout {} can't be played: {}", stage_id, error);
+	ui_writer.write(UIEvent { id: "info", text: format!("stage {} layout can't be played: {}", stage_id, error) });
+}
+
 fn read_gamestate_events(
 	mut gamestate_events: EventReader<GameStateEvent>,
 	mut event_writer: EventWriter<StageEvent>,
+	game_state: Res<GameState>,
+	asset_server: Res<AssetServer>,
+	mut game_rng: ResMut<GameRng>,
+	mut ui_writer: EventWriter<UIEvent>,
 	mut query: Query<&mut Stage>,
 ) {
 	let event_data: &GameStateData;
@@ -246,17 +538,27 @@ fn read_gamestate_events(
 		match event_data {
 			GameStateData::Init => {},
 			GameStateData::Setup (setup_data) => {
-				stage.load_layout(setup_data.stage_id);
-				stage.calculate_height_and_width_from_layout();
-				stage.calculate_camera_translation();
-				let width = stage.width;
-				let height = stage.height;
-				stage.walkable.init(width, height);
-				stage.stage_setting_data = StageSettingData::new();
-				stage.stage_setting_data.in_progress = true;
-				stage.stage_setting_data.current_line = stage.layout[0].clone();
-				
-				println!("stage: setting stage {}", stage.id);
+				game_rng.start_stage(game_state.campaign_index);
+				stage.colors = StageColors::from_metadata(&game_state.stage_metadata.colors);
+				stage.reveal = game_state.stage_metadata.reveal.unwrap_or_default();
+				stage.snack_count = game_state.stage_metadata.snack_count.unwrap_or(1).max(1) as usize;
+				stage.snack_weights = snack_weights(&game_state.stage_metadata);
+				stage.snack_placement = game_state.stage_metadata.snack_placement
+					.or(game_state.campaign.snack_placement)
+					.unwrap_or_default();
+				stage.snack_lifetime = game_state.stage_metadata.snack_lifetime.filter(|lifetime| *lifetime > 0.0);
+				stage.snack_countdown = game_state.stage_metadata.snack_countdown.unwrap_or(false);
+				stage.wrap_edges = game_state.stage_metadata.wrap_edges.unwrap_or(false);
+				// authored stages start setting in update_stage once the layout has loaded,
+				// generated ones can start right away.
+				match &setup_data.generator {
+					Some(params) => {
+						if let Err(error) = stage.generate_layout(setup_data.stage_id, params, &mut game_rng.visual) {
+							show_layout_error(setup_data.stage_id, &error, &mut ui_writer);
+						}
+					}
+					None => stage.load_layout(setup_data.stage_id, &asset_server),
+				}
 				break;
 			}
 			GameStateData::Start => {
@@ -264,31 +566,102 @@ fn read_gamestate_events(
 				stage.walkable.print();
 				break;
 			}
+			GameStateData::Edit(_edit_data) => {
+				// the editor only knows about the snack in the layout.
+				event_writer.write(StageEvent { data: StageEventData::ClearSnacks });
+				if let Some(coordinate) = stage.layout_snack() {
+					event_writer.write(StageEvent { data: StageEventData::SpawnSnack(coordinate, SnackKind::Normal) });
+				}
+			}
+			GameStateData::Rebind(_rebind_data) => {}
 			GameStateData::Play (_play_data)=> {
 				
 			}
 			GameStateData::Win ( _win_data)=> {
-				event_writer.write(StageEvent { data: StageEventData::ClearSnack });
+				event_writer.write(StageEvent { data: StageEventData::ClearSnacks });
 			}
 			GameStateData::Death => {
 			}
 			GameStateData::Reset(_counter) => {
-				event_writer.write(StageEvent { data: StageEventData::ClearSnack });
+				event_writer.write(StageEvent { data: StageEventData::ClearSnacks });
 			}
 		}
 	}
 }
 
+// editing a loaded layout file rebuilds the stage:
+// from scratch while it's being set, in place once it's been set.
+#[allow(clippy::too_many_arguments)]
+fn reload_stage_layout(
+	mut asset_events: EventReader<AssetEvent<StageLayout>>,
+	layouts: Res<Assets<StageLayout>>,
+	mut event_writer: EventWriter<StageEvent>,
+	mut game_state: ResMut<GameState>,
+	mut commands: Commands,
+	mut shared_assets: SharedAssets,
+	mut game_rng: ResMut<GameRng>,
+	mut ui_writer: EventWriter<UIEvent>,
+	mut stage_query: Query<&mut Stage>,
+	tile_query: Query<Entity, With<Tile>>,
+) {
+	for e in asset_events.read() {
+		let AssetEvent::Modified { id } = e else { continue; };
+		let Some(layout) = layouts.get(*id) else { continue; };
+
+		for mut stage in &mut stage_query {
+			if stage.layout_handle.id() != *id { continue; }
+			if stage.layout_pending {
+				// a rejected layout gets another go once the file has been changed.
+				stage.layout_rejected = false;
+				continue;
+			}
+
+			println!("stage: layout {} changed on disk, rebuilding", stage.id);
+			// the old tiles only go once the new layout turned out to be valid.
+			// the query doesn't see tiles spawned by the rebuild, so those stay.
+			match game_state.data {
+				GameStateData::Setup(_) => {
+					if let Err(error) = stage.start_setting(layout.rows.clone(), &mut game_rng.visual) {
+						show_layout_error(stage.id, &error, &mut ui_writer);
+						continue;
+					}
+					for entity in &tile_query { commands.entity(entity).despawn(); }
+					event_writer.write(StageEvent { data: StageEventData::ClearSnacks });
+				}
+				GameStateData::Start | GameStateData::Play(_) | GameStateData::Win(_) | GameStateData::Death => {
+					if let Err(error) = stage.rebuild(layout.rows.clone(), &mut commands, &mut shared_assets) {
+						show_layout_error(stage.id, &error, &mut ui_writer);
+						continue;
+					}
+					for entity in &tile_query { commands.entity(entity).despawn(); }
+					game_state.stage_width = stage.width;
+					game_state.stage_height = stage.height;
+					game_state.stage_behaviour = stage.behaviour.clone();
+					if let GameStateData::Play(play_data) = &mut game_state.data {
+						play_data.stage_behaviour = stage.behaviour.clone();
+					}
+				}
+				_ => {} // nothing set, or about to be set again anyway
+			}
+		}
+	}
+}
+
+#[allow(clippy::too_many_arguments)]
 fn update_stage(
 	mut event_writer: EventWriter<StageEvent>,
 	mut game_state: ResMut<GameState>,
 	time: Res<Time>,
+	layouts: Res<Assets<StageLayout>>,
+	asset_server: Res<AssetServer>,
 	mut commands: Commands,
-	mut meshes: ResMut<Assets<Mesh>>,
-	mut materials: ResMut<Assets<StandardMaterial>>,
+	mut shared_assets: SharedAssets,
 	mut clear_color: ResMut<ClearColor>,
+	mut game_rng: ResMut<GameRng>,
+	mut ui_writer: EventWriter<UIEvent>,
 	query: Query<(&mut Stage, &mut Transform)>
 ) {
+	let game_rng = &mut *game_rng;
 	for (mut stage, mut transform) in query {
 		match &mut game_state.data {
 			GameStateData::Setup(setup_data) => {
@@ -305,16 +678,45 @@ fn update_stage(
 				// animate clear color
 				let cc = clear_color.0.mix(&stage.colors.clear_color, time.delta_secs());
 				clear_color.0 = cc;
+				// wait for the layout before setting the stage
+				if stage.layout_pending {
+					if stage.layout_rejected { return; }
+					if let Some(layout) = layouts.get(&stage.layout_handle) {
+						// nothing is placed yet, so fast-forward can build the whole stage and go to Start.
+						let result = if setup_data.fast_forward {
+							validate_layout(&layout.rows).map(|()| {
+								let (stage_id, rows) = (stage.id, layout.rows.clone());
+								commands.queue(move |world: &mut World| {
+									build_stage_now(world, stage_id, rows).expect("stage: a validated layout failed to build");
+								});
+							})
+						} else {
+							stage.start_setting(layout.rows.clone(), &mut game_rng.visual)
+						};
+						if setup_data.fast_forward && result.is_ok() { return; }
+						if let Err(error) = result {
+							show_layout_error(stage.id, &error, &mut ui_writer);
+							stage.layout_rejected = true;
+							return;
+						}
+					} else if let LoadState::Failed(error) = asset_server.load_state(&stage.layout_handle) {
+						show_layout_error(stage.id, &error, &mut ui_writer);
+						stage.layout_rejected = true;
+						return;
+					} else {
+						return;
+					}
+				}
+
 				// tick stage setting
 				if setup_data.fast_forward {
-					for _i in 0..stage.get_tiles_left() {
-						stage.update_set_stage(&mut event_writer, &mut commands, &mut meshes, &mut materials, time.elapsed_secs(), setup_data.fast_forward);
-					}
+					stage.finish_setting(&mut event_writer, &mut commands, &mut shared_assets);
 				} else {
-					stage.update_set_stage(&mut event_writer, &mut commands, &mut meshes, &mut materials, time.elapsed_secs(), setup_data.fast_forward);
+					stage.update_set_stage(&mut event_writer, &mut commands, &mut shared_assets, time.elapsed_secs());
 				}
 
 				if !stage.stage_setting_data.in_progress {
+					stage.fill_snacks(&mut event_writer, &mut game_rng.gameplay, time.elapsed_secs());
 					setup_data.setup_done = true; // don't cross the event streams, maybe?
 					// accessing state data directly causes less bugs than writing and reading events in opposite direction between stage / state / snake.
 					// state -> stage & snake / stage -> snake works.
@@ -334,68 +736,72 @@ fn update_stage(
 
 				game_state.stage_width = stage.width;
 				game_state.stage_height = stage.height;
+				game_state.stage_behaviour = stage.behaviour.clone();
 
 				// we're spamming data here... but this state doesn't do much else except waiting for player to press play.
 
 				return;
 			}
+			GameStateData::Edit(edit_data) => {
+				// the editor starts from whatever is set right now.
+				if edit_data.rows.is_empty() {
+					edit_data.rows = stage.layout.clone();
+				}
+				return;
+			}
 			GameStateData::Play (play_data) => {				
-				let mut snake_data: Vec<(u32, &StageCoordinate, bool)> = vec![];
-				if play_data.snake1_data.active && !play_data.snake1_data.falling { snake_data.push((1, &play_data.snake1_data.coordinate, play_data.snake1_data.evaluate_move)) };
-				if play_data.snake2_data.active && !play_data.snake2_data.falling { snake_data.push((2, &play_data.snake2_data.coordinate, play_data.snake1_data.evaluate_move)) };
-				if play_data.snake3_data.active && !play_data.snake3_data.falling { snake_data.push((3, &play_data.snake3_data.coordinate, play_data.snake1_data.evaluate_move)) };
-
-				for (snake_id, snake_coordinate, evaluate_move) in snake_data {
-					// nothing to evaluate if snake hasn't moved
-					if !evaluate_move { return; }
-					
+				// crumbling tiles count down with snake moves
+				if play_data.last_move_time != stage.last_move_time {
+					stage.last_move_time = play_data.last_move_time;
+					let heads = snake_heads(play_data);
+					stage.update_crumbling_tiles(&heads, &mut event_writer, play_data, &mut game_rng.gameplay, time.elapsed_secs());
+					stage.update_doors(&mut event_writer, play_data, &mut game_rng.gameplay, time.elapsed_secs());
+					// snacks that found no place earlier get another go now that the snakes moved.
+					while stage.snacks.len() < stage.snack_count
+					&& stage.add_snack(&play_data.snakes_walkable_mask, &heads, &mut event_writer, &mut game_rng.gameplay, time.elapsed_secs()) {}
+				}
+
+				stage.update_snack_timers(&mut event_writer, play_data, &mut game_rng.gameplay, time.elapsed_secs());
+
+				// nothing to evaluate for snakes that haven't moved, or are already falling.
+				let moved: Vec<(u32, StageCoordinate)> = play_data.snakes.iter_mut()
+					.filter(|(_id, data)| data.active && !data.falling && data.evaluate_move)
+					.map(|(id, data)| {
+						data.evaluate_move = false;
+						(*id, data.coordinate)
+					})
+					.collect();
+
+				for (snake_id, snake_coordinate) in moved {
 					// falling snakes?
-					if !stage.walkable.get(snake_coordinate) {
-						// check if already falling
-						match snake_id {
-							1 => { if play_data.snake1_data.falling { continue; } }
-							2 => { if play_data.snake2_data.falling { continue; } }
-							3 => { if play_data.snake3_data.falling { continue; } }
-						    _=> {}
-						}
+					if !stage.walkable.get(&snake_coordinate) {
 						event_writer.write(StageEvent { data: StageEventData::SnakeFalling(snake_id) });
 						continue;
 					}
 					// snack eaten?
-					if snake_coordinate.equals(&stage.snack_coordinate) {
+					if let Some(snack_index) = stage.snacks.iter().position(|snack| snack.coordinate.equals(&snake_coordinate)) {
 						// increase score and movement speed, flag that it's time to update ui
-						play_data.score += 1;
-						play_data.move_speed += play_data.move_speed_increment;
-						play_data.someone_had_a_snack = true;
+						let eaten = stage.snacks.remove(snack_index);
+						play_data.snack_eaten(eaten.kind);
 						println!("... score is now {} of {}", play_data.score, play_data.goal);
 						println!("... move speed is now {}", play_data.move_speed);
 						// update ui
 						if play_data.score >= play_data.goal { 
-							event_writer.write(StageEvent { data: StageEventData::ClearSnack });
+							event_writer.write(StageEvent { data: StageEventData::ClearSnacks });
 							continue;
 						}
-						event_writer.write(StageEvent { data: StageEventData::SnackEaten(snake_id) });
-						stage.snack_coordinate = stage.get_next_snack_coordinate(play_data);
-						stage.snack_spawntime = time.elapsed_secs();
-						event_writer.write(StageEvent { data: StageEventData::SpawnSnack(stage.snack_coordinate) });
-						continue;
-					}
-
-					match snake_id {
-						1 => { play_data.snake1_data.evaluate_move = false; }
-						2 => { play_data.snake2_data.evaluate_move = false; }
-						3 => { play_data.snake3_data.evaluate_move = false; }
-						_=> ()
+						event_writer.write(StageEvent { data: StageEventData::SnackEaten(snake_id, eaten.kind) });
+						event_writer.write(StageEvent { data: StageEventData::RemoveSnack(eaten.coordinate) });
+						stage.add_snack(&play_data.snakes_walkable_mask, &snake_heads(play_data), &mut event_writer, &mut game_rng.gameplay, time.elapsed_secs());
 					}
 				}
 				return;
 			}
-			GameStateData::Win (win_data) => {
-				if time.elapsed_secs() >= stage.snack_spawntime + GLITTER_INTERVAL {
-					stage.snack_coordinate = stage.get_next_snack_coordinate(&win_data.play_data);
-					stage.snack_spawntime = time.elapsed_secs();
-					event_writer.write(StageEvent { data: StageEventData::SpawnSnack(stage.snack_coordinate) });
-				}
+			GameStateData::Win (win_data) if time.elapsed_secs() >= stage.snack_spawntime + GLITTER_INTERVAL => {
+				// a single snack glittering around the board
+				event_writer.write(StageEvent { data: StageEventData::ClearSnacks });
+				stage.snacks.clear();
+				stage.add_snack(&win_data.play_data.snakes_walkable_mask, &[], &mut event_writer, &mut game_rng.visual, time.elapsed_secs());
 			}
 			_=> {}
 		}
@@ -404,9 +810,46 @@ fn update_stage(
 
 fn update_tiles(
 	game_state: ResMut<GameState>,
+	mut stage_events: EventReader<StageEvent>,
 	mut commands: Commands,
-	query: Query<(Entity, &mut Tile, &mut Transform)>
+	mut shared_assets: SharedAssets,
+	mut game_rng: ResMut<GameRng>,
+	mut stage_query: Query<&mut Stage>,
+	mut query: Query<(Entity, &mut Tile, &mut Transform)>
 ) {
+	for e in stage_events.read() {
+		match e.data {
+			StageEventData::TilePainted(coordinate, c) => {
+				for (entity, tile, _transform) in &query {
+					if tile.coordinate.equals(&coordinate) {
+						commands.entity(entity).despawn();
+					}
+				}
+				for mut stage in &mut stage_query {
+					stage.paint_tile(coordinate, c, &mut commands, &mut shared_assets);
+				}
+			}
+			StageEventData::TileCollapsed(coordinate) => {
+				for (entity, mut tile, _transform) in &mut query {
+					if tile.coordinate.equals(&coordinate) && !tile.animated {
+						commands.entity(entity).insert(TumbleAnim::falling(1.0, &mut game_rng.visual));
+						tile.animated = true;
+					}
+				}
+			}
+			StageEventData::DoorOpened(coordinate) | StageEventData::DoorClosed(coordinate) => {
+				let y = if matches!(e.data, StageEventData::DoorOpened(_)) { TILE_Y } else { DOOR_CLOSED_Y };
+				for (entity, tile, _transform) in &query {
+					if tile.coordinate.equals(&coordinate) && !tile.animated {
+						let target = Vec3::new(coordinate.x as f32, y, coordinate.y as f32);
+						commands.entity(entity).insert(SlideAnim::new(target, DOOR_SLIDE_SPEED));
+					}
+				}
+			}
+			_ => {}
+		}
+	}
+
 	match &game_state.data {
 		GameStateData::Reset(_counter) => {
 			for (entity, _tile, _transform) in query {
@@ -428,7 +871,7 @@ fn update_tiles(
 		GameStateData::Win(win_data) => {
 			for (entity, mut tile, _transform) in query {
 				if !tile.animated {
-					commands.entity(entity).insert(TumbleAnim::new(win_data.tumble_speed, false));
+					commands.entity(entity).insert(TumbleAnim::new(win_data.tumble_speed, false, &mut game_rng.visual));
 					tile.animated = true;
 				}
 			}
@@ -466,36 +909,313 @@ impl Stage {
 	fn new() -> Self {
 		Self { 
 			id: 0,
+			layout_handle: Handle::default(),
+			layout_pending: false,
+			layout_rejected: false,
 			layout: vec![],
 			stage_setting_data: StageSettingData::new(),
+			reveal: RevealPattern::default(),
 			width: 0,
 			height: 0,
 			camera_translation: Vec3::new(0.0, 0.0, 0.0),
 			colors: StageColors::new(),
 			walkable: StageWalkableMask::new(0, 0),
-			snack_coordinate: StageCoordinate::new(0, 0),
+			behaviour: StageBehaviourMask::default(),
+			crumbling_tiles: vec![],
+			plates: vec![],
+			doors: vec![],
+			last_move_time: 0.0,
+			snacks: vec![],
+			snack_count: 1,
+			snack_weights: [1, 0, 0, 0, 0],
+			snack_placement: SnackPlacement::default(),
+			snack_lifetime: None,
+			snack_countdown: false,
+			wrap_edges: false,
 			snack_spawntime: 0.0
 		}
 	}
 
-	fn load_layout(&mut self, stage_id:u32) {
+	fn load_layout(&mut self, stage_id: u32, asset_server: &AssetServer) {
 		self.id = stage_id;
-
 		println!("stage: attempting to load layout for id {}", stage_id);
 
-		let path = format!("{}{}.txt", LAYOUT_FILEPATH, stage_id);
-		let layout = fs::read_to_string(path).expect("level layout {stage_id} not found!");
-		
-		println!("stage loaded layout {}:\n{}", stage_id, layout);
-		// TODO: validate layout
-		
-		self.layout = vec![];
-		for line in layout.lines() {
-			self.layout.push(String::from(line));
+		// a failed load means a missing or unreadable file, invalid layouts are caught in set_layout.
+		self.layout_rejected = false;
+		self.layout_handle = asset_server.load(format!("{}{}.txt", LAYOUT_FILEPATH, stage_id));
+		self.layout_pending = true;
+	}
+
+	fn generate_layout(&mut self, stage_id: u32, params: &GeneratorParams, rng: &mut StdRng) -> Result<(), LayoutError> {
+		self.id = stage_id;
+		println!("stage: generating layout for id {} with seed {}", stage_id, params.seed);
+
+		// drop the previous handle so hot reloading an authored layout leaves this stage alone.
+		self.layout_handle = Handle::default();
+		self.start_setting(generator::generate_layout(params), rng)
+	}
+
+	// an invalid layout leaves the stage as it was.
+	fn set_layout(&mut self, layout: Vec<String>) -> Result<(), LayoutError> {
+		validate_layout(&layout)?;
+		println!("stage loaded layout {}:\n{}", self.id, layout.join("\n"));
+		self.layout = layout;
+		self.layout_pending = false;
+		self.calculate_height_and_width_from_layout();
+		self.calculate_camera_translation();
+		self.walkable.init(self.width, self.height);
+		self.behaviour = StageBehaviourMask::from_layout(&self.layout, self.width, self.height);
+		self.last_move_time = 0.0;
+		self.crumbling_tiles.clear();
+		self.plates.clear();
+		self.doors.clear();
+		for (y, line) in self.layout.iter().enumerate() {
+			for (x, c) in line.chars().enumerate() {
+				let coordinate = StageCoordinate::new(x as i32, y as i32);
+				if c == CRUMBLING_TILE {
+					self.crumbling_tiles.push(CrumblingTile::new(coordinate));
+				} else if let Some(index) = plate_index(c) {
+					self.plates.push(Plate { coordinate, index });
+				} else if let Some((plate, open_by_default)) = door_link(c) {
+					self.doors.push(Door { coordinate, plate, open_by_default, open: open_by_default });
+				}
+			}
 		}
-		
-		self.height = self.layout.len();
-		self.width = self.layout[0].len();
+		Ok(())
+	}
+
+	// called once per move tick. any snake part on a plate flips the doors linked to it.
+	// a door doesn't close on a snake - it's held open until the head and body are off it,
+	// and closes on the first tick after that.
+	fn update_doors(&mut self,
+		event_writer: &mut EventWriter<StageEvent>,
+		play_data: &PlayData,
+		rng: &mut StdRng,
+		time: f32,
+	) {
+		// the snakes mask is false wherever a head or segment is.
+		let mut held = [false; PLATE_TILES.len()];
+		for plate in &self.plates {
+			held[plate.index] |= !play_data.snakes_walkable_mask.get(&plate.coordinate);
+		}
+
+		for i in 0..self.doors.len() {
+			let door = self.doors[i].clone();
+			let open = door.open_by_default != held[door.plate];
+			if open == door.open { continue; }
+			if !open && !play_data.snakes_walkable_mask.get(&door.coordinate) { continue; }
+
+			self.doors[i].open = open;
+			self.walkable.set(&door.coordinate, open);
+			if open {
+				event_writer.write(StageEvent { data: StageEventData::DoorOpened(door.coordinate) });
+			} else {
+				event_writer.write(StageEvent { data: StageEventData::DoorClosed(door.coordinate) });
+				self.relocate_snack_from(&door.coordinate, event_writer, play_data, rng, time);
+			}
+		}
+	}
+
+	// don't leave a snack floating over a tile that's gone.
+	fn relocate_snack_from(&mut self,
+		coordinate: &StageCoordinate,
+		event_writer: &mut EventWriter<StageEvent>,
+		play_data: &PlayData,
+		rng: &mut StdRng,
+		time: f32,
+	) {
+		let Some(index) = self.snacks.iter().position(|snack| snack.coordinate.equals(coordinate)) else { return; };
+		self.snacks.remove(index);
+		event_writer.write(StageEvent { data: StageEventData::RemoveSnack(*coordinate) });
+		self.add_snack(&play_data.snakes_walkable_mask, &snake_heads(play_data), event_writer, rng, time);
+	}
+
+	// snacks start their timer the first time they're seen in play - not while the stage
+	// waits for the players - and move somewhere else when it runs out. they only stay
+	// put if their tile is the last free one.
+	fn update_snack_timers(&mut self,
+		event_writer: &mut EventWriter<StageEvent>,
+		play_data: &PlayData,
+		rng: &mut StdRng,
+		time: f32,
+	) {
+		let Some(lifetime) = self.snack_lifetime else { return; };
+
+		for snack in self.snacks.iter_mut().filter(|snack| snack.expires_at.is_none()) {
+			let expires_at = time + lifetime;
+			snack.expires_at = Some(expires_at);
+			if self.snack_countdown {
+				event_writer.write(StageEvent { data: StageEventData::SnackCountdown(snack.coordinate, expires_at) });
+			}
+		}
+
+		let expired: Vec<StageCoordinate> = self.snacks.iter()
+			.filter(|snack| snack.expires_at.is_some_and(|expires_at| time >= expires_at))
+			.map(|snack| snack.coordinate)
+			.collect();
+		let heads = snake_heads(play_data);
+		for coordinate in expired {
+			self.snacks.retain(|snack| !snack.coordinate.equals(&coordinate));
+			event_writer.write(StageEvent { data: StageEventData::ExpireSnack(coordinate) });
+			let mut elsewhere_mask = play_data.snakes_walkable_mask.clone();
+			elsewhere_mask.set(&coordinate, false);
+			if self.get_next_snack_coordinate(&elsewhere_mask, &heads, rng).is_some() {
+				self.add_snack(&elsewhere_mask, &heads, event_writer, rng, time);
+			} else {
+				self.add_snack(&play_data.snakes_walkable_mask, &heads, event_writer, rng, time);
+			}
+		}
+	}
+
+	// returns false if there was nowhere to put it.
+	fn add_snack(&mut self,
+		snakes_walkable_mask: &StageWalkableMask,
+		heads: &[StageCoordinate],
+		event_writer: &mut EventWriter<StageEvent>,
+		rng: &mut StdRng,
+		time: f32,
+	) -> bool {
+		let Some(coordinate) = self.get_next_snack_coordinate(snakes_walkable_mask, heads, rng) else {
+			println!("stage: no free tile for a snack");
+			return false;
+		};
+		let kind = SnackKind::pick(&self.snack_weights, rng);
+		self.snacks.push(StageSnack { coordinate, kind, expires_at: None });
+		self.snack_spawntime = time;
+		event_writer.write(StageEvent { data: StageEventData::SpawnSnack(coordinate, kind) });
+		true
+	}
+
+	// the layout only has one snack, the rest of snack_count is placed once the stage is set.
+	// nothing is moving yet, so the spawn points stand in for the heads.
+	fn fill_snacks(&mut self, event_writer: &mut EventWriter<StageEvent>, rng: &mut StdRng, time: f32) {
+		let mut spawn_points_mask = StageWalkableMask::new(self.width, self.height);
+		let mut spawn_points: Vec<StageCoordinate> = vec![];
+		for (y, line) in self.layout.iter().enumerate() {
+			for (x, c) in line.chars().enumerate() {
+				if spawn_point_id(c).is_some() {
+					let coordinate = StageCoordinate::new(x as i32, y as i32);
+					spawn_points_mask.set(&coordinate, false);
+					spawn_points.push(coordinate);
+				}
+			}
+		}
+		while self.snacks.len() < self.snack_count
+		&& self.add_snack(&spawn_points_mask, &spawn_points, event_writer, rng, time) {}
+	}
+
+	// moves needed to get from a tile to every other one, going the way a head would -
+	// conveyors, portals, one-way tiles and wrapped edges included. None where it can't get to.
+	// tiles under snakes are in the way, the starting tile itself doesn't need to be free.
+	fn move_distances(&self, from: &StageCoordinate, snakes_walkable_mask: &StageWalkableMask) -> Vec<Vec<Option<u32>>> {
+		let mut distances: Vec<Vec<Option<u32>>> = vec![vec![None; self.width]; self.height];
+		if !self.walkable.contains(from) { return distances; }
+
+		distances[from.y as usize][from.x as usize] = Some(0);
+		let mut open = VecDeque::from([(*from, 0)]);
+
+		while let Some((coordinate, distance)) = open.pop_front() {
+			for direction in [Direction::Up, Direction::Down, Direction::Left, Direction::Right] {
+				let entered = step(&coordinate, &direction, &self.behaviour, self.wrap_edges);
+				let Some(next) = apply_tile_behaviour(entered, direction, &self.behaviour, self.wrap_edges) else { continue; };
+				// a snake on a conveyor or portal is in the way of a push or jump from it too.
+				if !self.walkable.get(&next) || !snakes_walkable_mask.get(&next) || !snakes_walkable_mask.get(&entered) { continue; }
+
+				let next_distance = &mut distances[next.y as usize][next.x as usize];
+				if next_distance.is_some() { continue; }
+				*next_distance = Some(distance + 1);
+				open.push_back((next, distance + 1));
+			}
+		}
+		distances
+	}
+
+	fn layout_snack(&self) -> Option<StageCoordinate> {
+		self.layout.iter().enumerate().find_map(|(y, line)| {
+			line.chars().position(|c| c == '*').map(|x| StageCoordinate::new(x as i32, y as i32))
+		})
+	}
+
+	// called once per move tick. a tile starts crumbling when a head leaves it,
+	// and collapses when its ticks run out - it stops being walkable, so anyone
+	// coming back falls through.
+	fn update_crumbling_tiles(&mut self,
+		heads: &[StageCoordinate],
+		event_writer: &mut EventWriter<StageEvent>,
+		play_data: &PlayData,
+		rng: &mut StdRng,
+		time: f32,
+	) {
+		let mut collapsed: Vec<StageCoordinate> = vec![];
+
+		for tile in &mut self.crumbling_tiles {
+			if tile.collapsed { continue; }
+
+			if heads.iter().any(|head| head.equals(&tile.coordinate)) {
+				tile.stepped_on = true;
+				tile.ticks_left = CRUMBLE_TICKS;
+			} else if tile.stepped_on {
+				tile.ticks_left -= 1;
+				if tile.ticks_left == 0 {
+					tile.collapsed = true;
+					collapsed.push(tile.coordinate);
+				}
+			}
+		}
+
+		for coordinate in collapsed {
+			println!("stage: tile {},{} collapsed", coordinate.x, coordinate.y);
+			self.walkable.set(&coordinate, false);
+			event_writer.write(StageEvent { data: StageEventData::TileCollapsed(coordinate) });
+			self.relocate_snack_from(&coordinate, event_writer, play_data, rng, time);
+		}
+	}
+
+	fn start_setting(&mut self, layout: Vec<String>, rng: &mut StdRng) -> Result<(), LayoutError> {
+		self.set_layout(layout)?;
+		self.snacks.clear();
+		self.stage_setting_data = StageSettingData::new();
+		self.stage_setting_data.in_progress = true;
+		self.stage_setting_data.order = reveal_order(self.reveal, self.width, self.height, rng);
+
+		println!("stage: setting stage {}", self.id);
+		Ok(())
+	}
+
+	// place every tile at once without spawn point or snack events,
+	// snakes and snacks stay where they are.
+	fn rebuild(&mut self,
+		layout: Vec<String>,
+		commands: &mut Commands,
+		shared_assets: &mut SharedAssets,
+	) -> Result<(), LayoutError> {
+		self.set_layout(layout)?;
+		for y in 0..self.height {
+			for x in 0..self.width {
+				let c = self.layout[y].chars().nth(x).unwrap_or('_');
+				if !self.spawn_tile(c, x, y, false, commands, shared_assets) {
+					self.walkable.set(&StageCoordinate::new(x as i32, y as i32), false);
+				}
+			}
+		}
+		Ok(())
+	}
+
+	// swap a single tile, the old one has to be despawned by the caller.
+	fn paint_tile(&mut self,
+		coordinate: StageCoordinate,
+		c: char,
+		commands: &mut Commands,
+		shared_assets: &mut SharedAssets,
+	) {
+		if !self.walkable.contains(&coordinate) { return; }
+		let (x, y) = (coordinate.x as usize, coordinate.y as usize);
+		self.layout[y] = self.layout[y].chars()
+			.enumerate()
+			.map(|(i, old)| if i == x { c } else { old })
+			.collect();
+		let walkable = self.spawn_tile(c, x, y, false, commands, shared_assets);
+		self.walkable.set(&coordinate, walkable);
 	}
 
 	fn calculate_height_and_width_from_layout(&mut self) {
@@ -505,8 +1225,9 @@ impl Stage {
 			return;
 		}
 		
+		// layouts are normalised when loaded, but don't trust the first row to be the widest.
 		self.height = self.layout.len();
-		self.width = self.layout[0].len();
+		self.width = self.layout.iter().map(|line| line.chars().count()).max().unwrap_or(0);
 
 		println!("stage height: {} width: {}", self.height, self.width);
 	} 
@@ -517,8 +1238,8 @@ impl Stage {
 		let mut x = self.width as f32;
 		let mut z = self.height as f32;
 		
-		x = if self.width % 2 == 0 { x / 2.0 } else { x / 2.0 - 0.5 };
-		z = if self.height % 2 == 0 { z / 2.0 } else { z / 2.0 - 0.5 };
+		x = if self.width.is_multiple_of(2) { x / 2.0 } else { x / 2.0 - 0.5 };
+		z = if self.height.is_multiple_of(2) { z / 2.0 } else { z / 2.0 - 0.5 };
 
 		let y = (z + x) * 1.68;
 
@@ -528,142 +1249,576 @@ impl Stage {
 		dbg!(self.camera_translation);
 	}
 
+	// the whole stage in one call: walkable mask, every tile, spawn point and snack events.
+	// nothing is animated, the stage is ready for Start as soon as this returns.
+	fn build_now(&mut self,
+		layout: Vec<String>,
+		event_writer: &mut EventWriter<StageEvent>,
+		commands: &mut Commands,
+		shared_assets: &mut SharedAssets,
+		rng: &mut StdRng,
+	) -> Result<(), LayoutError> {
+		self.start_setting(layout, rng)?;
+		self.finish_setting(event_writer, commands, shared_assets);
+		Ok(())
+	}
+
+	// place every tile that's left right away, for fast-forward.
+	fn finish_setting(&mut self,
+		event_writer: &mut EventWriter<StageEvent>,
+		commands: &mut Commands,
+		shared_assets: &mut SharedAssets,
+	) {
+		while self.stage_setting_data.in_progress {
+			self.place_next_tile(event_writer, commands, shared_assets, false);
+		}
+	}
+
 	// this looks a lot like it could be a system - 
 	// having access to self does make a lot of internal data 
 	// access much easier than handling refs and borrowing.
 	fn update_set_stage(&mut self,
 		event_writer: &mut EventWriter<StageEvent>,
 		commands: &mut Commands,
-		meshes: &mut ResMut<Assets<Mesh>>,
-		materials: &mut ResMut<Assets<StandardMaterial>>,
+		shared_assets: &mut SharedAssets,
 		time: f32,
-		fast_forward: bool,
 	) {
+		if !self.stage_setting_data.in_progress { return; }
+		if time < self.stage_setting_data.tile_placed_time + self.stage_setting_data.interval { return; }
+
+		let drop = self.reveal == RevealPattern::Drop;
+		self.place_next_tile(event_writer, commands, shared_assets, drop);
+
 		let data = &mut self.stage_setting_data;
-		if !data.in_progress { return; }
-		if !fast_forward && time < data.tile_placed_time + data.interval { return; }
-		
-		// set tile at current x and y
-		let c = data.current_line.chars()
-			.nth(data.x)
+		if data.interval > 0.01 {
+			data.interval *= 0.86;
+		} else {
+			data.interval = 0.001; // tick more or less every frame for the rest
+		}
+		data.tile_placed_time = time;
+	}
+
+	fn place_next_tile(&mut self,
+		event_writer: &mut EventWriter<StageEvent>,
+		commands: &mut Commands,
+		shared_assets: &mut SharedAssets,
+		drop: bool,
+	) {
+		// set the next tile in reveal order
+		let (x, y) = self.stage_setting_data.order[self.stage_setting_data.next];
+		let c = self.layout[y].chars()
+			.nth(x)
 			.unwrap_or('_');
+		let coordinate = StageCoordinate::new(x as i32, y as i32);
 
-		match c {
-			'A' | 'a' => {
-				commands.spawn((
-					Tile::new(),
-					Mesh3d(meshes.add(Cuboid::new(TILE_SIZE, TILE_SIZE, TILE_SIZE))),
-					MeshMaterial3d(materials.add(self.colors.tiles_a)),
-					Transform::from_xyz(data.x as f32, 0.5, data.y as f32), // coordinate swizzle xyz to xzy - top down view
-				));
-			}
-			'B' | 'b' => {
-				commands.spawn((
-					Tile::new(),
-					Mesh3d(meshes.add(Cuboid::new(TILE_SIZE, TILE_SIZE, TILE_SIZE))),
-					MeshMaterial3d(materials.add(self.colors.tiles_b)),
-					Transform::from_xyz(data.x as f32, 0.5, data.y as f32),
-				));
-			}
-			'C' | 'c' => {
-				commands.spawn((
-					Tile::new(),
-					Mesh3d(meshes.add(Cuboid::new(TILE_SIZE, TILE_SIZE, TILE_SIZE))),
-					MeshMaterial3d(materials.add(self.colors.tiles_c)),
-					Transform::from_xyz(data.x as f32, 0.5, data.y as f32),
-				));
-			}
-			'1' => {
-				commands.spawn((
-					Tile::new(),
-					Mesh3d(meshes.add(Cuboid::new(TILE_SIZE, TILE_SIZE, TILE_SIZE))),
-					MeshMaterial3d(materials.add(self.colors.tiles_a)),
-					Transform::from_xyz(data.x as f32, 0.5, data.y as f32),
-				));
-				let snake_spawn_point_data = SnakeSpawnPointData{ snake_id: 1, spawn_point: StageCoordinate::new(data.x as i32, data.y as i32) };
+		if self.spawn_tile(c, x, y, drop, commands, shared_assets) {
+			if let Some(snake_id) = spawn_point_id(c) {
+				let snake_spawn_point_data = SnakeSpawnPointData{ snake_id, spawn_point: coordinate };
 				event_writer.write(StageEvent { data: StageEventData::SetSnakeSpawnPoint(snake_spawn_point_data) });
+			} else if c == '*' {
+				// the layout snack is always a plain one.
+				event_writer.write(StageEvent { data: StageEventData::SpawnSnack(coordinate, SnackKind::Normal) });
+				self.snacks.push(StageSnack { coordinate, kind: SnackKind::Normal, expires_at: None });
 			}
-			'2' => {
-				commands.spawn((
-					Tile::new(),
-					Mesh3d(meshes.add(Cuboid::new(TILE_SIZE, TILE_SIZE, TILE_SIZE))),
-					MeshMaterial3d(materials.add(self.colors.tiles_a)),
-					Transform::from_xyz(data.x as f32, 0.5, data.y as f32),
-				));
-				let snake_spawn_point_data = SnakeSpawnPointData{ snake_id: 2, spawn_point: StageCoordinate::new(data.x as i32, data.y as i32) };
-				event_writer.write(StageEvent { data: StageEventData::SetSnakeSpawnPoint(snake_spawn_point_data) });
-			}
-			'3' => {
-				commands.spawn((
-					Tile::new(),
-					Mesh3d(meshes.add(Cuboid::new(TILE_SIZE, TILE_SIZE, TILE_SIZE))),
-					MeshMaterial3d(materials.add(self.colors.tiles_a)),
-					Transform::from_xyz(data.x as f32, 0.5, data.y as f32),
-				));
-				let snake_spawn_point_data = SnakeSpawnPointData{ snake_id: 3, spawn_point: StageCoordinate::new(data.x as i32, data.y as i32) };
-				event_writer.write(StageEvent { data: StageEventData::SetSnakeSpawnPoint(snake_spawn_point_data) });
-			}
-			'*' => {
-				commands.spawn((
-					Tile::new(),
-					Mesh3d(meshes.add(Cuboid::new(TILE_SIZE, TILE_SIZE, TILE_SIZE))),
-					MeshMaterial3d(materials.add(self.colors.tiles_a)),
-					Transform::from_xyz(data.x as f32, 0.5, data.y as f32),
-				));
-				event_writer.write(StageEvent { data: StageEventData::SpawnSnack(StageCoordinate::new(data.x as i32, data.y as i32)) });
-				self.snack_coordinate.x = data.x as i32;
-				self.snack_coordinate.y = data.y as i32;
-			}
-			_ => {
-				// if no tile was placed, mark the coordinate as non-walkable:
-				self.walkable.rows[data.y].tiles[data.x] = false;
-			}
+		} else {
+			// if no tile was placed, mark the coordinate as non-walkable:
+			self.walkable.set(&coordinate, false);
 		}
 
-		// tick x and y
-		if data.x < self.width - 1 { // move through line
-			data.x += 1;
-		} else if data.y < self.height - 1 { // get next line
-			data.x = 0;
-			data.y += 1;
-			data.current_line = self.layout[data.y].clone();
-		} else { // done!
-			data.in_progress = false; 
+		let data = &mut self.stage_setting_data;
+		data.next += 1;
+		if data.next >= data.order.len() { // done!
+			data.in_progress = false;
 		}
+	}
 
-		if data.interval > 0.01 {
-			data.interval *= 0.86;
-		} else {
-			data.interval = 0.001; // tick more or less every frame for the rest
+	// spawns the tile for a layout character, returns false if the coordinate can't be walked on
+	// (void, or a closed door - which still gets a tile, sunk below the floor).
+	// dropped tiles start high up and slide into place.
+	fn spawn_tile(&self,
+		c: char,
+		x: usize,
+		y: usize,
+		drop: bool,
+		commands: &mut Commands,
+		shared_assets: &mut SharedAssets,
+	) -> bool {
+		let color = match c {
+			'A' | 'a' | '*' => self.colors.tiles_a,
+			CRUMBLING_TILE => CRUMBLING_COLOR,
+			'B' | 'b' => self.colors.tiles_b,
+			'C' | 'c' => self.colors.tiles_c,
+			c if spawn_point_id(c).is_some() => self.colors.tiles_a,
+			c if portal_index(c).is_some() => PORTAL_COLORS[portal_index(c).unwrap()],
+			c if plate_index(c).is_some() => PLATE_COLORS[plate_index(c).unwrap()],
+			c if door_link(c).is_some() => DOOR_COLORS[door_link(c).unwrap().0],
+			c if conveyor_direction(c).is_some() || one_way_direction(c).is_some() => self.colors.tiles_a,
+			_ => return false,
+		};
+
+		let closed_door = door_link(c).is_some_and(|(_plate, open)| !open);
+		let translation = Vec3::new(x as f32, if closed_door { DOOR_CLOSED_Y } else { TILE_Y }, y as f32); // coordinate swizzle xyz to xzy - top down view
+		let mut tile = commands.spawn((
+			Tile::new(StageCoordinate::new(x as i32, y as i32)),
+			Mesh3d(shared_assets.cuboid(Vec3::splat(TILE_SIZE))),
+			MeshMaterial3d(shared_assets.material(color)),
+			Transform::from_translation(if drop { translation + Vec3::Y * DROP_HEIGHT } else { translation }),
+		));
+		if drop {
+			tile.insert(SlideAnim::new(translation, DROP_SPEED));
 		}
-		data.tile_placed_time = time;
-	}
 
-	fn get_tiles_left(&mut self) -> usize {
-		self.height * self.width - ( (self.stage_setting_data.x + 1) + (self.stage_setting_data.y + 1) )
+		// conveyor and one-way tiles get a small marker on top, nudged towards their direction.
+		let marker = match (conveyor_direction(c), one_way_direction(c)) {
+			(Some(direction), _) => Some((direction, CONVEYOR_MARKER_COLOR)),
+			(_, Some(direction)) => Some((direction, ONE_WAY_MARKER_COLOR)),
+			_ => None,
+		};
+		if let Some((direction, marker_color)) = marker {
+			let offset = match direction {
+				Direction::Up => Vec3::new(0.0, 0.0, -0.25),
+				Direction::Down => Vec3::new(0.0, 0.0, 0.25),
+				Direction::Left => Vec3::new(-0.25, 0.0, 0.0),
+				Direction::Right => Vec3::new(0.25, 0.0, 0.0),
+				Direction::None => Vec3::ZERO,
+			};
+			tile.with_child((
+				Mesh3d(shared_assets.cuboid(MARKER_SIZE)),
+				MeshMaterial3d(shared_assets.material(marker_color)),
+				Transform::from_translation(Vec3::new(0.0, TILE_SIZE / 2.0, 0.0) + offset),
+			));
+		}
+		!closed_door
 	}
 
-	fn get_next_snack_coordinate(&mut self, play_data: &PlayData) -> StageCoordinate {
-		let mut rng = rand::rng();
-		let mut candidates: Vec<StageCoordinate> = vec![];
+	// a free tile at least one of the heads can get to. if none of them can get anywhere,
+	// any free tile will do - the snakes might open a way later. None if nothing is free.
+	// fair placement narrows it down to tiles that are about as far from every head.
+	fn get_next_snack_coordinate(&self,
+		snakes_walkable_mask: &StageWalkableMask,
+		heads: &[StageCoordinate],
+		rng: &mut StdRng,
+	) -> Option<StageCoordinate> {
+		let mut free: Vec<StageCoordinate> = vec![];
 
 		for y in 0..self.height {
-			if play_data.snakes_walkable_mask.rows.len() <= y { break; }
-			
 			for x in 0..self.width {
-				if play_data.snakes_walkable_mask.rows[y].tiles.len() <= x { break; }
-				
+				// get() is false outside the mask, so a snake mask of another size is safe here.
 				let coordinate = StageCoordinate::new(x as i32, y as i32);
-				if play_data.snakes_walkable_mask.rows[y].tiles[x] && self.walkable.get(&coordinate) {
-					candidates.push(coordinate);
-				}			
+				if snakes_walkable_mask.get(&coordinate) && self.walkable.get(&coordinate)
+				&& !self.snacks.iter().any(|snack| snack.coordinate.equals(&coordinate)) {
+					free.push(coordinate);
+				}
 			}
 		}
+		if free.is_empty() { return None; }
+
+		let distances: Vec<Vec<Vec<Option<u32>>>> = heads.iter()
+			.map(|head| self.move_distances(head, snakes_walkable_mask))
+			.collect();
+		let reachable: Vec<StageCoordinate> = free.iter()
+			.filter(|c| distances.iter().any(|d| d[c.y as usize][c.x as usize].is_some()))
+			.copied()
+			.collect();
 
-		if !candidates.is_empty() {
-			let ri = rng.random_range(0..candidates.len());
-			candidates[ri]
+		if heads.is_empty() { return free.choose(rng).copied(); }
+		if reachable.is_empty() {
+			println!("stage: no free tile reachable from the heads, placing the snack anywhere");
+			return free.choose(rng).copied();
+		}
+
+		let SnackPlacement::Fair(max_difference) = self.snack_placement else {
+			return reachable.choose(rng).copied();
+		};
+		if heads.len() < 2 { return reachable.choose(rng).copied(); }
+
+		// how much closer the nearest head is than the farthest, for tiles every head can get to.
+		let spreads: Vec<(StageCoordinate, u32)> = reachable.iter()
+			.filter_map(|c| {
+				let moves: Option<Vec<u32>> = distances.iter().map(|d| d[c.y as usize][c.x as usize]).collect();
+				let moves = moves?;
+				Some((*c, moves.iter().max()? - moves.iter().min()?))
+			})
+			.collect();
+		if spreads.is_empty() { return reachable.choose(rng).copied(); }
+
+		// nothing within the limit: the fairest there is.
+		let limit = max_difference.max(spreads.iter().map(|(_, spread)| *spread).min().unwrap());
+		let fair: Vec<StageCoordinate> = spreads.iter()
+			.filter(|(_, spread)| *spread <= limit)
+			.map(|(c, _)| *c)
+			.collect();
+		fair.choose(rng).copied()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use bevy::ecs::system::{ RunSystemOnce, SystemState };
+	use crate::players::PlayersConfig;
+	use crate::shared_assets::SharedAssetCache;
+
+	fn rng() -> StdRng { StdRng::seed_from_u64(1) }
+
+	fn stage(layout: &str) -> Stage {
+		let mut stage = Stage::new();
+		stage.set_layout(crate::layout::parse_layout(layout)).unwrap();
+		stage
+	}
+
+	fn play_data(stage: &Stage) -> PlayData {
+		let game_state = GameState { stage_width: stage.width, stage_height: stage.height, ..default() };
+		PlayData::new(&game_state, &PlayersConfig { count: 1 })
+	}
+
+	// stage methods write events, this catches them without running an app.
+	fn stage_events(f: impl FnOnce(&mut EventWriter<StageEvent>)) -> Vec<StageEventData> {
+		let mut world = World::new();
+		world.init_resource::<Events<StageEvent>>();
+		let mut system_state: SystemState<EventWriter<StageEvent>> = SystemState::new(&mut world);
+		f(&mut system_state.get_mut(&mut world));
+		let events = world.resource::<Events<StageEvent>>();
+		events.get_cursor().read(events).map(|e| e.data.clone()).collect()
+	}
+
+	#[test]
+	fn every_reveal_pattern_places_every_tile_once() {
+		let patterns = [RevealPattern::Rows, RevealPattern::Spiral, RevealPattern::Scatter, RevealPattern::Diagonal, RevealPattern::Drop];
+		for pattern in patterns {
+			for (width, height) in [(1, 1), (7, 3), (3, 7), (14, 19)] {
+				let mut order = reveal_order(pattern, width, height, &mut rng());
+				assert_eq!(order.len(), width * height, "{pattern:?} {width}x{height}");
+				order.sort();
+				order.dedup();
+				assert_eq!(order.len(), width * height, "{pattern:?} {width}x{height} repeats tiles");
+			}
+		}
+	}
+
+	#[test]
+	fn spiral_starts_in_the_center() {
+		assert_eq!(reveal_order(RevealPattern::Spiral, 5, 5, &mut rng())[..3], [(2, 2), (3, 2), (3, 3)]);
+	}
+
+	fn open_stage(width: usize, height: usize) -> Stage {
+		let mut stage = Stage::new();
+		stage.width = width;
+		stage.height = height;
+		stage.walkable = StageWalkableMask::new(width, height);
+		stage.behaviour = StageBehaviourMask::new(width, height);
+		stage
+	}
+
+	#[test]
+	fn snacks_only_go_where_a_head_can_get_to() {
+		let mut rng = rng();
+		// a snake body across column 2 seals the left side off from the head on the right.
+		let stage = open_stage(5, 4);
+		let mut snakes_walkable_mask = StageWalkableMask::new(5, 4);
+		for y in 0..4 {
+			snakes_walkable_mask.set(&StageCoordinate::new(2, y), false);
+		}
+		let heads = [StageCoordinate::new(4, 0)];
+
+		for _ in 0..50 {
+			let coordinate = stage.get_next_snack_coordinate(&snakes_walkable_mask, &heads, &mut rng).unwrap();
+			assert!(coordinate.x > 2, "{coordinate:?} can't be reached");
+		}
+
+		// with every head boxed in, any free tile beats no snack at all.
+		snakes_walkable_mask.set(&StageCoordinate::new(4, 1), false);
+		snakes_walkable_mask.set(&StageCoordinate::new(3, 0), false);
+		assert!(stage.get_next_snack_coordinate(&snakes_walkable_mask, &heads, &mut rng).is_some());
+	}
+
+	#[test]
+	fn fair_snacks_sit_between_the_heads() {
+		let mut rng = rng();
+		let mut stage = open_stage(7, 1);
+		stage.snack_placement = SnackPlacement::Fair(0);
+		let snakes_walkable_mask = StageWalkableMask::new(7, 1);
+		let heads = [StageCoordinate::new(0, 0), StageCoordinate::new(6, 0)];
+
+		for _ in 0..20 {
+			assert_eq!(stage.get_next_snack_coordinate(&snakes_walkable_mask, &heads, &mut rng), Some(StageCoordinate::new(3, 0)));
+		}
+
+		stage.snack_placement = SnackPlacement::Fair(2);
+		for _ in 0..50 {
+			let coordinate = stage.get_next_snack_coordinate(&snakes_walkable_mask, &heads, &mut rng).unwrap();
+			assert!((2..=4).contains(&coordinate.x), "{coordinate:?} is too close to one head");
+		}
+	}
+
+	#[test]
+	fn no_snack_coordinate_without_a_free_tile() {
+		let mut rng = rng();
+		let stage = open_stage(3, 1);
+		let mut snakes_walkable_mask = StageWalkableMask::new(3, 1);
+		for x in 0..3 {
+			snakes_walkable_mask.set(&StageCoordinate::new(x, 0), false);
+		}
+		assert_eq!(stage.get_next_snack_coordinate(&snakes_walkable_mask, &[StageCoordinate::new(0, 0)], &mut rng), None);
+	}
+
+	#[test]
+	fn crumbling_tiles_collapse_a_few_moves_after_a_head_leaves() {
+		let mut stage = stage("1%A*");
+		let play_data = play_data(&stage);
+		let tile = StageCoordinate::new(1, 0);
+		let mut rng = rng();
+
+		// never stepped on, never collapses
+		for _ in 0..CRUMBLE_TICKS + 1 {
+			assert!(stage_events(|events| stage.update_crumbling_tiles(&[StageCoordinate::new(0, 0)], events, &play_data, &mut rng, 0.0)).is_empty());
+		}
+
+		// standing on it keeps it up, the countdown starts once the head moves on.
+		for _ in 0..CRUMBLE_TICKS + 1 {
+			stage_events(|events| stage.update_crumbling_tiles(&[tile], events, &play_data, &mut rng, 0.0));
+		}
+		for _ in 0..CRUMBLE_TICKS - 1 {
+			assert!(stage_events(|events| stage.update_crumbling_tiles(&[StageCoordinate::new(2, 0)], events, &play_data, &mut rng, 0.0)).is_empty());
+			assert!(stage.walkable.get(&tile));
+		}
+		let events = stage_events(|events| stage.update_crumbling_tiles(&[StageCoordinate::new(2, 0)], events, &play_data, &mut rng, 0.0));
+		assert!(matches!(events[..], [StageEventData::TileCollapsed(coordinate)] if coordinate == tile));
+		assert!(!stage.walkable.get(&tile));
+
+		// collapsed for good
+		assert!(stage_events(|events| stage.update_crumbling_tiles(&[tile], events, &play_data, &mut rng, 0.0)).is_empty());
+	}
+
+	#[test]
+	fn plates_flip_doors_while_held() {
+		let mut stage = stage("1oOeA*");
+		let mut play_data = play_data(&stage);
+		let plate = StageCoordinate::new(1, 0);
+		let (closed_door, open_door) = (StageCoordinate::new(2, 0), StageCoordinate::new(3, 0));
+		stage.walkable.set(&closed_door, false); // as placed by the stage setting
+		let mut rng = rng();
+
+		assert!(stage_events(|events| stage.update_doors(events, &play_data, &mut rng, 0.0)).is_empty());
+
+		play_data.snakes_walkable_mask.set(&plate, false);
+		let events = stage_events(|events| stage.update_doors(events, &play_data, &mut rng, 0.0));
+		assert!(matches!(events[..], [StageEventData::DoorOpened(a), StageEventData::DoorClosed(b)] if a == closed_door && b == open_door));
+		assert!(stage.walkable.get(&closed_door));
+		assert!(!stage.walkable.get(&open_door));
+		// still held, nothing changes
+		assert!(stage_events(|events| stage.update_doors(events, &play_data, &mut rng, 0.0)).is_empty());
+
+		play_data.snakes_walkable_mask.set(&plate, true);
+		let events = stage_events(|events| stage.update_doors(events, &play_data, &mut rng, 0.0));
+		assert!(matches!(events[..], [StageEventData::DoorClosed(a), StageEventData::DoorOpened(b)] if a == closed_door && b == open_door));
+		assert!(!stage.walkable.get(&closed_door));
+		assert!(stage.walkable.get(&open_door));
+	}
+
+	#[test]
+	fn plates_only_flip_their_own_doors() {
+		let mut stage = stage("1oOA*\nApPAA");
+		let mut play_data = play_data(&stage);
+		let (o_plate, o_door) = (StageCoordinate::new(1, 0), StageCoordinate::new(2, 0));
+		let (p_plate, p_door) = (StageCoordinate::new(1, 1), StageCoordinate::new(2, 1));
+		stage.walkable.set(&o_door, false);
+		stage.walkable.set(&p_door, false);
+		let mut rng = rng();
+
+		play_data.snakes_walkable_mask.set(&o_plate, false);
+		let events = stage_events(|events| stage.update_doors(events, &play_data, &mut rng, 0.0));
+		assert!(matches!(events[..], [StageEventData::DoorOpened(door)] if door == o_door));
+		assert!(stage.walkable.get(&o_door));
+		assert!(!stage.walkable.get(&p_door));
+
+		// both held, then only the other one.
+		play_data.snakes_walkable_mask.set(&p_plate, false);
+		let events = stage_events(|events| stage.update_doors(events, &play_data, &mut rng, 0.0));
+		assert!(matches!(events[..], [StageEventData::DoorOpened(door)] if door == p_door));
+		play_data.snakes_walkable_mask.set(&o_plate, true);
+		let events = stage_events(|events| stage.update_doors(events, &play_data, &mut rng, 0.0));
+		assert!(matches!(events[..], [StageEventData::DoorClosed(door)] if door == o_door));
+		assert!(stage.walkable.get(&p_door));
+	}
+
+	#[test]
+	fn doors_stay_open_under_a_snake() {
+		let mut stage = stage("1oeA*");
+		let mut play_data = play_data(&stage);
+		let (plate, door) = (StageCoordinate::new(1, 0), StageCoordinate::new(2, 0));
+		let mut rng = rng();
+
+		play_data.snakes_walkable_mask.set(&plate, false);
+		play_data.snakes_walkable_mask.set(&door, false);
+		assert!(stage_events(|events| stage.update_doors(events, &play_data, &mut rng, 0.0)).is_empty());
+		assert!(stage.walkable.get(&door));
+
+		// the snake moves off the door, but keeps the plate down.
+		play_data.snakes_walkable_mask.set(&door, true);
+		let events = stage_events(|events| stage.update_doors(events, &play_data, &mut rng, 0.0));
+		assert!(matches!(events[..], [StageEventData::DoorClosed(coordinate)] if coordinate == door));
+		assert!(!stage.walkable.get(&door));
+	}
+
+	#[test]
+	fn closing_doors_move_their_snack() {
+		let mut stage = stage("1oeA*");
+		let mut play_data = play_data(&stage);
+		let (plate, door) = (StageCoordinate::new(1, 0), StageCoordinate::new(2, 0));
+		stage.snacks.push(StageSnack { coordinate: door, kind: SnackKind::Normal, expires_at: None });
+		let mut rng = rng();
+
+		play_data.snakes_walkable_mask.set(&plate, false);
+		let events = stage_events(|events| stage.update_doors(events, &play_data, &mut rng, 0.0));
+		assert!(matches!(events[..], [
+			StageEventData::DoorClosed(_),
+			StageEventData::RemoveSnack(removed),
+			StageEventData::SpawnSnack(added, _),
+		] if removed == door && added != door && added != plate));
+		assert_eq!(stage.snacks.len(), 1);
+		assert_ne!(stage.snacks[0].coordinate, door);
+	}
+
+	#[test]
+	fn snacks_count_down_and_move_when_they_expire() {
+		let mut stage = stage("1AAA*");
+		stage.snack_lifetime = Some(5.0);
+		stage.snack_countdown = true;
+		let snack = StageCoordinate::new(4, 0);
+		stage.snacks.push(StageSnack { coordinate: snack, kind: SnackKind::Normal, expires_at: None });
+		let play_data = play_data(&stage);
+		let mut rng = rng();
+
+		// the timer starts the first time the snack is seen in play
+		let events = stage_events(|events| stage.update_snack_timers(events, &play_data, &mut rng, 1.0));
+		assert!(matches!(events[..], [StageEventData::SnackCountdown(coordinate, expires_at)] if coordinate == snack && expires_at == 6.0));
+		assert_eq!(stage.snacks[0].expires_at, Some(6.0));
+		assert!(stage_events(|events| stage.update_snack_timers(events, &play_data, &mut rng, 5.9)).is_empty());
+
+		let events = stage_events(|events| stage.update_snack_timers(events, &play_data, &mut rng, 6.0));
+		assert!(matches!(events[..], [
+			StageEventData::ExpireSnack(expired),
+			StageEventData::SpawnSnack(added, _),
+		] if expired == snack && added != snack));
+		assert_eq!(stage.snacks.len(), 1);
+		assert_ne!(stage.snacks[0].coordinate, snack);
+		assert_eq!(stage.snacks[0].expires_at, None);
+	}
+
+	#[test]
+	fn expiring_snacks_stay_on_the_last_free_tile() {
+		let mut stage = stage("1*");
+		stage.snack_lifetime = Some(5.0);
+		let snack = StageCoordinate::new(1, 0);
+		stage.snacks.push(StageSnack { coordinate: snack, kind: SnackKind::Normal, expires_at: Some(5.0) });
+		let mut play_data = play_data(&stage);
+		play_data.snakes_walkable_mask.set(&StageCoordinate::new(0, 0), false);
+		let mut rng = rng();
+
+		let events = stage_events(|events| stage.update_snack_timers(events, &play_data, &mut rng, 5.0));
+		assert!(matches!(events[..], [
+			StageEventData::ExpireSnack(expired),
+			StageEventData::SpawnSnack(added, _),
+		] if expired == snack && added == snack));
+	}
+
+	#[test]
+	fn invalid_layouts_leave_the_stage_as_it_was() {
+		let mut stage = Stage::new();
+		stage.set_layout(vec![String::from("1A*")]).unwrap();
+
+		let error = stage.set_layout(vec![String::from("AA?")]).unwrap_err();
+		assert_eq!(error.problems.len(), 3); // no spawn point, no snack, unknown tile
+		assert_eq!(stage.layout, vec![String::from("1A*")]);
+		assert_eq!(stage.width, 3);
+	}
+
+	#[test]
+	fn fast_forward_builds_a_loaded_stage_straight_to_start() {
+		use crate::state::SetupData;
+
+		let mut app = App::new();
+		app.add_plugins((MinimalPlugins, AssetPlugin::default()));
+		app.init_asset::<Mesh>();
+		app.init_asset::<StandardMaterial>();
+		app.init_asset::<StageLayout>();
+		app.init_resource::<SharedAssetCache>();
+		app.init_resource::<ClearColor>();
+		app.init_resource::<GameState>();
+		app.insert_resource(GameRng::new(1));
+		app.add_event::<StageEvent>();
+		app.add_event::<GameStateEvent>();
+		app.add_event::<UIEvent>();
+
+		let world = app.world_mut();
+		let mut stage = Stage::new();
+		stage.id = 3;
+		stage.layout_handle = world.resource_mut::<Assets<StageLayout>>().add(StageLayout { rows: crate::layout::parse_layout("1A*\n2AB") });
+		stage.layout_pending = true;
+		world.spawn((stage, Transform::default()));
+		world.resource_mut::<GameState>().data = GameStateData::Setup(SetupData {
+			stage_id: 3,
+			generator: None,
+			spotlight_translation: Vec3::ZERO,
+			spotlight_intensity_multiplier: 1.0,
+			setup_done: false,
+			fast_forward: true,
+		});
+
+		world.run_system_once(update_stage).unwrap();
+
+		let game_state = world.resource::<GameState>();
+		assert!(matches!(game_state.data, GameStateData::Start));
+		assert_eq!((game_state.stage, game_state.stage_width, game_state.stage_height), (3, 3, 2));
+		let stage = world.query::<&Stage>().single(world).unwrap();
+		assert!(!stage.layout_pending);
+		assert!(!stage.stage_setting_data.in_progress);
+		assert_eq!(world.query::<&Tile>().iter(world).count(), 6);
+	}
+
+	#[test]
+	fn build_now_sets_the_whole_stage_in_one_call() {
+		let mut app = App::new();
+		app.add_plugins((MinimalPlugins, AssetPlugin::default()));
+		app.init_asset::<Mesh>();
+		app.init_asset::<StandardMaterial>();
+		app.init_resource::<SharedAssetCache>();
+		app.add_event::<StageEvent>();
+
+		let stage = app.world_mut().run_system_once(|
+			mut event_writer: EventWriter<StageEvent>,
+			mut commands: Commands,
+			mut shared_assets: SharedAssets,
+		| {
+			let mut stage = Stage::new();
+			stage.reveal = RevealPattern::Drop;
+			let layout = vec![String::from("1A_"), String::from("2*B")];
+			stage.build_now(layout, &mut event_writer, &mut commands, &mut shared_assets, &mut rng()).unwrap();
+			stage
+		}).unwrap();
+
+		assert!(!stage.stage_setting_data.in_progress);
+		assert!(stage.walkable.get(&StageCoordinate::new(1, 0)));
+		assert!(!stage.walkable.get(&StageCoordinate::new(2, 0)));
+		assert_eq!(stage.snacks, vec![StageSnack { coordinate: StageCoordinate::new(1, 1), kind: SnackKind::Normal, expires_at: None }]);
+
+		let world = app.world_mut();
+		let tiles = world.query::<&Tile>().iter(world).count();
+		assert_eq!(tiles, 5);
+		// nothing left falling into place
+		assert_eq!(world.query::<&SlideAnim>().iter(world).count(), 0);
+
+		let events = world.resource::<Events<StageEvent>>();
+		let mut spawn_points = 0;
+		let mut snacks = 0;
+		for e in events.get_cursor().read(events) {
+			match e.data {
+				StageEventData::SetSnakeSpawnPoint(_) => spawn_points += 1,
+				StageEventData::SpawnSnack(_, _) => snacks += 1,
+				_ => {}
+			}
 		}
-		else { StageCoordinate::new(0, 0) }
+		assert_eq!((spawn_points, snacks), (2, 1));
 	}
 }
@@ -1,14 +1,27 @@
 use bevy::{input::keyboard::KeyboardInput, prelude::*};
+use bevy::asset::io::file::FileAssetReader;
+use std::collections::BTreeMap;
 use std::fs;
+use std::path::PathBuf;
 
-use crate::stage::{ StageCoordinate, StageWalkableMask};
+use crate::actions::{ Action, ActionEvent, CONTROLS_KEY, EDIT_STAGE_KEY };
+use crate::campaign::Campaign;
+use crate::editor::EditData;
+use crate::generator::GeneratorParams;
+use crate::metadata::StageMetadata;
+use crate::players::PlayersConfig;
+use crate::rebind::RebindData;
+use crate::snacks::SnackKind;
+use crate::stage::{ StageBehaviourMask, StageCoordinate, StageWalkableMask};
 use crate::ui::{ UIEvent };
 
 // state plugin: game loop and shared data.
 
-const STARTING_STAGE_PATH: &str = "./assets/save_data/starting_stage.txt";
-const STAGE_LAYOUTS_PATH: &str = "./assets/stage_layouts";
+const STARTING_STAGE_PATH: &str = "save_data/starting_stage.txt";
 const DEFAULT_MOVE_INTERVAL: f32 = 0.5;
+const MIN_MOVE_SPEED: f32 = 0.5;
+const SLOW_DOWN_INCREMENTS: f32 = 4.0; // a slow-down snack takes off this many speed increments, all told
+const BONUS_TIME: f32 = 5.0; // seconds
 
 pub struct StatePlugin;
 
@@ -27,6 +40,8 @@ pub enum GameStateData {
 	Init,
 	Setup(SetupData),
 	Start,
+	Edit(EditData),
+	Rebind(RebindData),
 	Play(PlayData),
 	Win(WinData),
 	Death,
@@ -51,20 +66,27 @@ pub struct GameStateEvent {
 
 #[derive(Resource, Default)]
 pub struct GameState {
-	pub stage: u32,
+	pub stage: u32, // id of the current stage
 	pub stage_width: usize,
 	pub stage_height: usize,
-	pub final_stage: u32,
+	pub stage_behaviour: StageBehaviourMask,
+	pub campaign: Campaign,
+	pub campaign_index: usize, // position of the current stage in the campaign
+	pub final_stage: usize, // campaign index of the last stage, usize::MAX for endless campaigns
+	pub generator: Option<GeneratorParams>, // set when the current stage is generated instead of authored
+	pub stage_metadata: StageMetadata,
 	pub data: GameStateData,
+	pub paused: bool, // only while playing, update_gamestate keeps virtual time in step with it
 }
 
 impl GameState {
-	fn set_data(&mut self, 
+	pub fn set_data(&mut self, 
 		data: GameStateData,
 		event_writer: &mut EventWriter<GameStateEvent>,
 		ui_writer: &mut EventWriter<UIEvent>,
 	)
 	{
+		if !matches!(data, GameStateData::Play(_)) { self.paused = false; }
 		event_writer.write(GameStateEvent { data: data.clone() });
 		self.data = data;
 
@@ -74,33 +96,45 @@ impl GameState {
 			}
 			GameStateData::Setup (setup_data) => {
 				println!("game state: Setup stage {}", &setup_data.stage_id);
-				ui_writer.write(UIEvent{ id: "stage", text: format!("stage {}", &self.stage) });
+				ui_writer.write(UIEvent{ id: "stage", text: self.stage_display_name() });
 				ui_writer.write(UIEvent { id: "info", text: String::from("setting stage...") });
 			},
 			GameStateData::Start => {
 				println!("game state: Start");
 				ui_writer.write(UIEvent{ id: "header", text: String::from("START") });
-				ui_writer.write(UIEvent{ id: "sub_header", text: String::from("press space") });
-				ui_writer.write(UIEvent { id: "info", text: String::from("Player 1: Arrows - Player 2: WASD - Player 3: IJKL") });
+				ui_writer.write(UIEvent{ id: "sub_header", text: String::from("press space or A") });
+				// the info line lists the keys, the rebind plugin has them.
+			},
+			GameStateData::Edit (edit_data) => {
+				println!("game state: Edit stage {}", self.stage);
+				ui_writer.write(UIEvent{ id: "header", text: String::from("EDIT") });
+				ui_writer.write(UIEvent{ id: "sub_header", text: format!("brush: {}", edit_data.brush) });
+				ui_writer.write(UIEvent { id: "info", text: String::from("arrows / mouse: move - type a tile: paint - space: brush - ctrl+s: save - esc: back") });
+			},
+			GameStateData::Rebind(_rebind_data) => {
+				println!("game state: Rebind");
+				ui_writer.write(UIEvent{ id: "header", text: String::from("CONTROLS") });
+				ui_writer.write(UIEvent{ id: "sub_header", text: String::from("") });
+				ui_writer.write(UIEvent { id: "info", text: String::from("up / down: pick - enter: rebind - esc: save and back") });
 			},
 			GameStateData::Play (play_data) => {
 				println!("game state: Play stage {} goal {}", &play_data.stage_id, &play_data.goal);
 				ui_writer.write(UIEvent{ id: "header", text: String::from("") });
 				ui_writer.write(UIEvent{ id: "sub_header", text: String::from("") });
 				ui_writer.write(UIEvent { id: "score", text: format!("0 of {}", play_data.goal) });
-				ui_writer.write(UIEvent { id: "info", text: String::from("") });
+				ui_writer.write(UIEvent { id: "info", text: time_left_text(play_data.time_left) });
 			},
 			GameStateData::Win (win_data) => {
 				println!("game state: Win stage {}", &win_data.play_data.stage_id);
 				ui_writer.write(UIEvent{ id: "header", text: String::from("WIN!") });
-				ui_writer.write(UIEvent{ id: "sub_header", text: String::from("press space") });
+				ui_writer.write(UIEvent{ id: "sub_header", text: String::from("press space or A") });
 				ui_writer.write(UIEvent { id: "score", text: String::from("") });
 				ui_writer.write(UIEvent { id: "stage", text: String::from("") });
 			},
 			GameStateData::Death => {
 				println!("game state: Death");
 				ui_writer.write(UIEvent{ id: "header", text: String::from("DEATH") });
-				ui_writer.write(UIEvent{ id: "sub_header", text: String::from("press space") });
+				ui_writer.write(UIEvent{ id: "sub_header", text: String::from("press space or A") });
 				ui_writer.write(UIEvent { id: "score", text: String::from("") });
 				ui_writer.write(UIEvent { id: "stage", text: String::from("") });
 			},
@@ -114,23 +148,69 @@ impl GameState {
 			}
 		}
 	}
+
+	fn set_campaign_index(&mut self, index: usize) {
+		self.campaign_index = index;
+		let stage = self.campaign.stage_at(index);
+		self.stage = stage.id;
+		self.generator = stage.generator;
+		self.stage_metadata = StageMetadata::load(self.stage);
+	}
+
+	// campaign title first, metadata name for stages without one.
+	// generated stages also show their seed, so a good one can be played again.
+	fn stage_display_name(&self) -> String {
+		let title = self.campaign.stage_at(self.campaign_index).title;
+		let name = self.stage_metadata.display_name(self.stage, title.as_deref());
+		match self.generator {
+			Some(params) => format!("{} (seed {})", name, params.seed),
+			None => name,
+		}
+	}
 }
 
 fn init_gamestate() {
 	println!("starting snakes game!");
 }
 
+#[allow(clippy::too_many_arguments)]
 fn update_gamestate(
 	mut event_writer: EventWriter<GameStateEvent>,
 	mut ui_writer: EventWriter<UIEvent>,
 	mut game_state: ResMut<GameState>,
 	mut key_events: EventReader<KeyboardInput>,
+	mut action_events: EventReader<ActionEvent>,
+	players: Res<PlayersConfig>,
+	time: Res<Time>,
+	mut virtual_time: ResMut<Time<Virtual>>,
 ) {
 	// This is similar to the classic gamestate switch in engines like unity - 
 	// makes the branching readable inside one function.
 	// Using bevy State pattern the branching would move to Plugin.build(),
 	// separating it completely from each of the branch cases as separate systems.
 
+	// confirm and pause come from any player, keyboard or gamepad.
+	// editor keys stay on the keyboard. only fresh presses count, so the release of
+	// a fast-forward press can't start the stage too, and a held key can't go on to the next screen.
+	let actions: Vec<Action> = action_events.read().map(|e| e.action).collect();
+	let confirmed = actions.contains(&Action::Confirm);
+	let keys_pressed: Vec<KeyCode> = key_events.read()
+		.filter(|e| e.state.is_pressed() && !e.repeat)
+		.map(|e| e.key_code)
+		.collect();
+
+	// pausing stops virtual time - movement, timers and animations all run on it.
+	if actions.contains(&Action::Pause) && matches!(game_state.data, GameStateData::Play(_)) {
+		game_state.paused = !game_state.paused;
+		if game_state.paused {
+			ui_writer.write(UIEvent { id: "header", text: String::from("PAUSED") });
+			ui_writer.write(UIEvent { id: "sub_header", text: String::from("press esc or start") });
+		} else {
+			ui_writer.write(UIEvent { id: "header", text: String::from("") });
+			ui_writer.write(UIEvent { id: "sub_header", text: String::from("") });
+		}
+	}
+
 	match &mut game_state.data {
 		GameStateData::Init => {
 			ui_writer.write(UIEvent { id: "header", text: String::from("") });
@@ -139,120 +219,160 @@ fn update_gamestate(
 			ui_writer.write(UIEvent { id: "stage", text: String::from("") });
 			ui_writer.write(UIEvent { id: "info", text: String::from("") });
 			
-			game_state.final_stage = get_number_of_stages() - 1;
+			game_state.campaign = Campaign::load();
+			game_state.final_stage = match game_state.campaign.endless {
+				Some(_) => usize::MAX,
+				None => game_state.campaign.stages.len() - 1,
+			};
 			let saved_stage = load_starting_stage();
-			game_state.stage = if saved_stage <= game_state.final_stage { saved_stage } else { 0 };
-			let initial_setup_data = GameStateData::Setup(SetupData::new(game_state.stage));
+			let index = game_state.campaign.index_of(saved_stage).unwrap_or(0);
+			game_state.set_campaign_index(index);
+			let initial_setup_data = GameStateData::Setup(SetupData::new(&game_state));
 			game_state.set_data(initial_setup_data, &mut event_writer, &mut ui_writer);
 		}
 		GameStateData::Setup(setup_data) => {
-			for e in key_events.read() {
-				if e.key_code == KeyCode::Space {
-					setup_data.fast_forward = true;
-				}
+			if confirmed {
+				setup_data.fast_forward = true;
 			}
 
-			let go_to_start;
-
-			if setup_data.fast_forward {
-				go_to_start = setup_data.setup_done && setup_data.fast_forward_buffer >= 10; // insert a couple of "buffer" frames so input events don't overlap - tried key_events.clear()
-				setup_data.fast_forward_buffer += 1;
-			} else { go_to_start = setup_data.setup_done; }
-			
-			if go_to_start {
+			if setup_data.setup_done {
 				game_state.set_data(GameStateData::Start, &mut event_writer, &mut ui_writer); 
 			}
 		}
 		GameStateData::Start => {
-			for e in key_events.read() {
-				if e.key_code == KeyCode::Space {
-					let stage = game_state.stage;
-					let width = game_state.stage_width;
-					let height = game_state.stage_height;
-					game_state.set_data(GameStateData::Play(PlayData::new(stage, width, height)), &mut event_writer, &mut ui_writer);
-				}
+			if confirmed {
+				let play_data = PlayData::new(&game_state, &players);
+				game_state.set_data(GameStateData::Play(play_data), &mut event_writer, &mut ui_writer);
+			} else if keys_pressed.contains(&EDIT_STAGE_KEY) {
+				game_state.set_data(GameStateData::Edit(EditData::new()), &mut event_writer, &mut ui_writer);
+			} else if keys_pressed.contains(&CONTROLS_KEY) {
+				game_state.set_data(GameStateData::Rebind(RebindData::new()), &mut event_writer, &mut ui_writer);
+			}
+		}
+		GameStateData::Edit (edit_data) => {
+			if keys_pressed.contains(&KeyCode::Escape) {
+				// setting the stage again picks up a saved layout, and drops anything unsaved.
+				if edit_data.unsaved { println!("editor: leaving with unsaved changes"); }
+				game_state.set_data(GameStateData::Reset(0), &mut event_writer, &mut ui_writer);
 			}
-		} 
+		}
+		GameStateData::Rebind(rebind_data) => {
+			// the rebind plugin reads the keys, and says when it's done.
+			if rebind_data.done {
+				game_state.set_data(GameStateData::Start, &mut event_writer, &mut ui_writer);
+			}
+		}
 		GameStateData::Play (play_data) => {
+			// count down stages with a time limit, the ui only needs whole seconds.
+			let mut time_changed = false;
+			if let Some(time_left) = &mut play_data.time_left {
+				let shown = time_left.ceil();
+				*time_left -= time.delta_secs();
+				time_changed = time_left.ceil() != shown;
+			}
+
 			if play_data.score >= play_data.goal {
 				println!("Cleared stage {}!", play_data.stage_id);
 				let win_data = WinData::new(play_data.clone());
 				game_state.set_data(GameStateData::Win(win_data), &mut event_writer, &mut ui_writer);
+			} else if play_data.time_left.is_some_and(|time_left| time_left <= 0.0) {
+				println!("out of time on stage {}!", play_data.stage_id);
+				game_state.set_data(GameStateData::Death, &mut event_writer, &mut ui_writer);
 			} else if play_data.crash || play_data.all_falling {
 				game_state.set_data(GameStateData::Death, &mut event_writer, &mut ui_writer);
 			} else if play_data.someone_had_a_snack {
 				ui_writer.write(UIEvent { id: "score", text: format!("{} of {}", play_data.score, play_data.goal) });
+				ui_writer.write(UIEvent { id: "info", text: time_left_text(play_data.time_left) });
 				play_data.someone_had_a_snack = false;
+			} else if time_changed {
+				ui_writer.write(UIEvent { id: "info", text: time_left_text(play_data.time_left) });
 			}
 		}
 		GameStateData::Win (_win_data) => {
-			for e in key_events.read() {
-				if e.key_code == KeyCode::Space {
-					if game_state.stage < game_state.final_stage { game_state.stage += 1 };
-					game_state.set_data(GameStateData::Reset(0), &mut event_writer, &mut ui_writer);
+			if confirmed {
+				if game_state.campaign_index < game_state.final_stage {
+					let next_index = game_state.campaign_index + 1;
+					game_state.set_campaign_index(next_index);
 				}
+				game_state.set_data(GameStateData::Reset(0), &mut event_writer, &mut ui_writer);
 			}
 		}
 		GameStateData::Death => {
-			for e in key_events.read() {
-				if e.key_code == KeyCode::Space {
-					game_state.set_data(GameStateData::Reset(0), &mut event_writer, &mut ui_writer);
-				}
+			if confirmed {
+				game_state.set_data(GameStateData::Reset(0), &mut event_writer, &mut ui_writer);
 			}
 		}
 		GameStateData::Reset(counter) => {
 			*counter += 1;
 			if *counter >= 30 {
-				let stage = game_state.stage;
-				game_state.set_data(GameStateData::Setup(SetupData::new(stage)), &mut event_writer, &mut ui_writer);
+				let setup_data = SetupData::new(&game_state);
+				game_state.set_data(GameStateData::Setup(setup_data), &mut event_writer, &mut ui_writer);
 			}
 		}
 	}
+
+	// set_data drops the pause on the way out of play, wherever it's called from.
+	if game_state.paused != virtual_time.is_paused() {
+		if game_state.paused { virtual_time.pause(); } else { virtual_time.unpause(); }
+	}
+}
+
+fn time_left_text(time_left: Option<f32>) -> String {
+	match time_left {
+		Some(time_left) => format!("time: {}", time_left.max(0.0).ceil()),
+		None => String::from(""),
+	}
+}
+
+// files read outside the asset server resolve against the same root it uses,
+// so they are found no matter which directory the game was started from.
+pub fn asset_path(path: &str) -> PathBuf {
+	FileAssetReader::get_base_path().join("assets").join(path)
 }
 
 fn load_starting_stage() -> u32 {
 	// liking rust here - this is so short and sweet!
-	let savedata = fs::read_to_string(STARTING_STAGE_PATH).unwrap_or_default();
+	let savedata = fs::read_to_string(asset_path(STARTING_STAGE_PATH)).unwrap_or_default();
 	savedata.parse::<u32>().unwrap_or(0)
 }
 
-fn get_number_of_stages() -> u32 {
-	let dir = fs::read_dir(STAGE_LAYOUTS_PATH);
-	let paths = match dir {
-		Ok(p) => p,
-		Err(error) => panic!("Stage layouts not found! {error}"),
-	};
-	paths.count() as u32
-}
-
 #[derive(Debug, Clone, Copy)]
 pub struct SetupData {
 	pub stage_id: u32,
+	pub generator: Option<GeneratorParams>,
 	pub spotlight_translation: Vec3,
 	pub spotlight_intensity_multiplier: f32,
 	pub setup_done: bool,
 	pub fast_forward: bool,
-	pub fast_forward_buffer: u32,
 }
 
 impl SetupData {
-	fn new(stage_id: u32) -> Self {
-		Self {
-			stage_id,
-			spotlight_translation: match stage_id {
+	fn new(game_state: &GameState) -> Self {
+		let stage_id = game_state.stage;
+		let metadata = &game_state.stage_metadata;
+
+		// hard-coded values are fallbacks for stages without metadata.
+		let spotlight_translation = match metadata.spotlight_translation {
+			Some((x, y, z)) => Vec3::new(x, y, z),
+			None => match stage_id {
 				5 => Vec3::new(14.0, 16.0, 12.0),
 				6 => Vec3::new(20.0, 12.0, 4.0),
 				_ => Vec3::new(6.0, 8.0, 4.0),	
-			},	
-			spotlight_intensity_multiplier: match stage_id {
+			},
+		};
+
+		Self {
+			stage_id,
+			generator: game_state.generator,
+			spotlight_translation,
+			spotlight_intensity_multiplier: metadata.spotlight_intensity_multiplier.unwrap_or(match stage_id {
 				1 => 0.5,
 				5 => 2.4,
 				6 => 6.0,
 				_ => 1.0,
-			},
+			}),
 			setup_done: false,
 			fast_forward: false,
-			fast_forward_buffer: 0,
 		}
 	}
 }
@@ -266,18 +386,20 @@ pub struct PlayData {
 	pub move_speed_increment: f32,
 	pub move_interval: f32,
 	pub last_move_time: f32,
-	pub snake1_data: SnakePlayData,
-	pub snake2_data: SnakePlayData,
-	pub snake3_data: SnakePlayData,
+	pub snakes: BTreeMap<u32, SnakePlayData>, // by snake id, ordered so every run goes through them the same way
 	pub snakes_walkable_mask: StageWalkableMask,
+	pub stage_behaviour: StageBehaviourMask,
+	pub wrap_edges: bool,
+	pub time_left: Option<f32>, // seconds, on stages with a time limit
 	pub crash: bool,
 	pub all_falling: bool,
 	pub someone_had_a_snack: bool,
 }
 
 impl PlayData {
-	fn new(stage_id: u32, stage_width: usize, stage_height: usize) -> Self {
-		let gameplay_config = GameplayConfig::new(stage_id);
+	pub fn new(game_state: &GameState, players: &PlayersConfig) -> Self {
+		let stage_id = game_state.stage;
+		let gameplay_config = GameplayConfig::new(stage_id, &game_state.stage_metadata);
 
 		Self {
 			stage_id,
@@ -287,20 +409,41 @@ impl PlayData {
 			move_speed_increment: gameplay_config.speed_increment,
 			move_interval: DEFAULT_MOVE_INTERVAL / gameplay_config.start_speed,
 			last_move_time: 0.0,
-			snake1_data: SnakePlayData::new(),
-			snake2_data: SnakePlayData::new(),
-			snake3_data: SnakePlayData::new(),
-			snakes_walkable_mask: StageWalkableMask::new(stage_width, stage_height),
+			snakes: players.ids().map(|id| (id, SnakePlayData::new())).collect(),
+			snakes_walkable_mask: StageWalkableMask::new(game_state.stage_width, game_state.stage_height),
+			stage_behaviour: game_state.stage_behaviour.clone(),
+			wrap_edges: game_state.stage_metadata.wrap_edges.unwrap_or(false),
+			time_left: game_state.stage_metadata.time_limit,
 			crash: false,
 			all_falling: false,
 			someone_had_a_snack: false,
 		}
 	}
 
+	// what every snack does as soon as it's eaten. the snake applies its kind after that,
+	// and that's when the move interval catches up with the speed.
+	pub fn snack_eaten(&mut self, kind: SnackKind) {
+		self.score += kind.score();
+		self.move_speed += self.move_speed_increment;
+		self.someone_had_a_snack = true;
+	}
+
 	pub fn increment_speed(&mut self) {
 		self.move_speed += self.move_speed_increment;
 		self.move_interval = if self.move_speed > 0.01 { DEFAULT_MOVE_INTERVAL / self.move_speed } else { DEFAULT_MOVE_INTERVAL / 0.01 };
 	}
+
+	// takes back the increment snack_eaten added too, so the player loses SLOW_DOWN_INCREMENTS in all.
+	pub fn slow_down(&mut self) {
+		self.move_speed = (self.move_speed - self.move_speed_increment * (SLOW_DOWN_INCREMENTS + 1.0)).max(MIN_MOVE_SPEED);
+		self.move_interval = DEFAULT_MOVE_INTERVAL / self.move_speed;
+	}
+
+	pub fn add_bonus_time(&mut self) {
+		if let Some(time_left) = &mut self.time_left {
+			*time_left += BONUS_TIME;
+		}
+	}
 }
 
 #[derive(Debug, Clone)]
@@ -340,8 +483,18 @@ struct GameplayConfig {
 }
 
 impl GameplayConfig {
-	fn new(stage_id: u32) -> Self {
-		match stage_id { // TODO: shared config stor
+	fn new(stage_id: u32, metadata: &StageMetadata) -> Self {
+		let fallback = Self::fallback(stage_id);
+		Self {
+			goal: metadata.goal.unwrap_or(fallback.goal),
+			start_speed: metadata.start_speed.unwrap_or(fallback.start_speed),
+			speed_increment: metadata.speed_increment.unwrap_or(fallback.speed_increment),
+		}
+	}
+
+	// used for stages without metadata, or metadata that leaves values out.
+	fn fallback(stage_id: u32) -> Self {
+		match stage_id {
 			0 => { Self { goal: 1, start_speed: 1.0, speed_increment: 0.1 } }
 			1 => { Self { goal: 5, start_speed: 1.0, speed_increment: 0.12 } }
 			2 => { Self { goal: 24, start_speed: 1.8, speed_increment: 0.04 } }
@@ -373,3 +526,156 @@ impl WinData {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::layout::parse_layout;
+	use crate::rng::GameRng;
+	use crate::shared_assets::SharedAssetCache;
+	use crate::stage::{ build_stage_now, StageEvent };
+	use bevy::ecs::system::RunSystemOnce;
+
+	fn headless_app() -> App {
+		let mut app = App::new();
+		app.add_plugins((MinimalPlugins, AssetPlugin::default()));
+		app.init_asset::<Mesh>();
+		app.init_asset::<StandardMaterial>();
+		app.init_resource::<SharedAssetCache>();
+		app.init_resource::<GameState>();
+		app.insert_resource(GameRng::new(1));
+		app.add_event::<StageEvent>();
+		app.add_event::<GameStateEvent>();
+		app.add_event::<UIEvent>();
+		app
+	}
+
+	fn play_data(time_limit: Option<f32>) -> PlayData {
+		let mut game_state = GameState::default();
+		game_state.stage_metadata.time_limit = time_limit;
+		let mut play_data = PlayData::new(&game_state, &PlayersConfig { count: 1 });
+		play_data.move_speed = 3.0;
+		play_data.move_speed_increment = 0.1;
+		play_data
+	}
+
+	#[test]
+	fn slow_down_snacks_take_off_four_increments() {
+		let mut play_data = play_data(None);
+		play_data.snack_eaten(SnackKind::SlowDown);
+		play_data.slow_down();
+		assert!((play_data.move_speed - 2.6).abs() < 0.0001, "{}", play_data.move_speed);
+		assert!((play_data.move_interval - DEFAULT_MOVE_INTERVAL / 2.6).abs() < 0.0001);
+		assert_eq!(play_data.score, 1);
+
+		play_data.move_speed = MIN_MOVE_SPEED + 0.1;
+		play_data.slow_down();
+		assert_eq!(play_data.move_speed, MIN_MOVE_SPEED);
+	}
+
+	#[test]
+	fn golden_snacks_score_more() {
+		let mut play_data = play_data(None);
+		play_data.snack_eaten(SnackKind::Golden);
+		assert_eq!(play_data.score, SnackKind::Golden.score());
+		assert!(play_data.score > 1);
+		assert!(play_data.someone_had_a_snack);
+	}
+
+	#[test]
+	fn bonus_time_only_counts_with_a_time_limit() {
+		let mut timed = play_data(Some(10.0));
+		timed.add_bonus_time();
+		assert_eq!(timed.time_left, Some(10.0 + BONUS_TIME));
+
+		let mut untimed = play_data(None);
+		untimed.add_bonus_time();
+		assert_eq!(untimed.time_left, None);
+	}
+
+	#[test]
+	fn running_out_of_time_is_a_death() {
+		let mut app = headless_app();
+		app.add_event::<KeyboardInput>();
+		app.add_event::<ActionEvent>();
+		app.insert_resource(PlayersConfig { count: 1 });
+
+		app.world_mut().resource_mut::<GameState>().data = GameStateData::Play(play_data(Some(0.5)));
+		app.world_mut().run_system_once(update_gamestate).unwrap();
+		assert!(matches!(app.world().resource::<GameState>().data, GameStateData::Play(_)));
+
+		app.world_mut().resource_mut::<GameState>().data = GameStateData::Play(play_data(Some(0.0)));
+		app.world_mut().run_system_once(update_gamestate).unwrap();
+		assert!(matches!(app.world().resource::<GameState>().data, GameStateData::Death));
+	}
+
+	#[test]
+	fn held_keys_do_not_change_screens() {
+		use bevy::input::{ ButtonState, keyboard::Key };
+
+		let mut app = headless_app();
+		app.add_event::<KeyboardInput>();
+		app.add_event::<ActionEvent>();
+		app.insert_resource(PlayersConfig { count: 1 });
+		app.world_mut().resource_mut::<GameState>().data = GameStateData::Start;
+
+		let key_c = |repeat| KeyboardInput {
+			key_code: KeyCode::KeyC,
+			logical_key: Key::Character("c".into()),
+			state: ButtonState::Pressed,
+			text: None,
+			repeat,
+			window: Entity::PLACEHOLDER,
+		};
+		app.world_mut().send_event(key_c(true));
+		app.world_mut().run_system_once(update_gamestate).unwrap();
+		assert!(matches!(app.world().resource::<GameState>().data, GameStateData::Start));
+
+		app.world_mut().send_event(key_c(false));
+		app.world_mut().run_system_once(update_gamestate).unwrap();
+		assert!(matches!(app.world().resource::<GameState>().data, GameStateData::Rebind(_)));
+	}
+
+	#[test]
+	fn leaving_play_unpauses() {
+		let mut app = headless_app();
+		app.add_event::<KeyboardInput>();
+		app.add_event::<ActionEvent>();
+		app.insert_resource(PlayersConfig { count: 1 });
+		app.world_mut().resource_mut::<GameState>().data = GameStateData::Play(play_data(None));
+
+		app.world_mut().send_event(ActionEvent { snake_id: None, action: Action::Pause });
+		app.world_mut().run_system_once(update_gamestate).unwrap();
+		assert!(app.world().resource::<GameState>().paused);
+		assert!(app.world().resource::<Time<Virtual>>().is_paused());
+
+		let mut play_data = play_data(None);
+		play_data.crash = true;
+		app.world_mut().resource_mut::<GameState>().data = GameStateData::Play(play_data);
+		app.world_mut().run_system_once(update_gamestate).unwrap();
+		assert!(matches!(app.world().resource::<GameState>().data, GameStateData::Death));
+		assert!(!app.world().resource::<GameState>().paused);
+		assert!(!app.world().resource::<Time<Virtual>>().is_paused());
+	}
+
+	#[test]
+	fn a_built_stage_goes_straight_to_play() {
+		let mut app = headless_app();
+		build_stage_now(app.world_mut(), 3, parse_layout("1A_x\n2*Bx")).unwrap();
+
+		let game_state = app.world().resource::<GameState>();
+		assert!(matches!(game_state.data, GameStateData::Start));
+		assert_eq!((game_state.stage, game_state.stage_width, game_state.stage_height), (3, 4, 2));
+
+		let play_data = PlayData::new(game_state, &PlayersConfig { count: 2 });
+		assert_eq!(play_data.snakes.len(), 2);
+		assert_eq!(play_data.stage_behaviour.get(&StageCoordinate::new(3, 0)), crate::stage::TileBehaviour::Portal(StageCoordinate::new(3, 1)));
+	}
+
+	#[test]
+	fn an_invalid_stage_is_not_built() {
+		let mut app = headless_app();
+		assert!(build_stage_now(app.world_mut(), 3, parse_layout("AA?")).is_err());
+		assert!(matches!(app.world().resource::<GameState>().data, GameStateData::Init));
+	}
+}
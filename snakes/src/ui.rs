@@ -75,6 +75,20 @@ fn init_ui_elements(mut commands: Commands) {
 		));
 	});
 
+	commands.spawn((
+		Node {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(20.0),
+            left: Val::Percent(35.0),
+            ..default()
+        },
+	)).with_children(|builder| {
+		builder.spawn((
+			UIElement::new("list"),
+			Text::new(""),
+		));
+	});
+
 	let container = commands.spawn((
 		Node {
 			width: Val::Percent(100.0),